@@ -0,0 +1,184 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use meshview::bit_grid::BitGrid;
+use meshview::voxel_manager::{VoxelManager, VOXEL_WIDTH};
+use nalgebra::Vector3;
+
+
+const SIZES: [(usize, usize, usize); 3] = [(16, 16, 10), (32, 32, 20), (50, 50, 30)];
+
+
+#[derive(Clone, Copy, Debug)]
+enum Scene {
+    Empty,
+    HalfFull,
+    Full,
+    Avalanche,
+}
+
+impl Scene {
+    const ALL: [Scene; 4] = [Scene::Empty, Scene::HalfFull, Scene::Full, Scene::Avalanche];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Scene::Empty => "empty",
+            Scene::HalfFull => "half_full",
+            Scene::Full => "full",
+            Scene::Avalanche => "avalanche",
+        }
+    }
+
+    /// Empty, half and full grids are at rest; the avalanche is a solid block
+    /// hanging in the upper half of the box, so every tick moves voxels.
+    fn build(&self, (length, width, height): (usize, usize, usize)) -> VoxelManager {
+        let mut manager = VoxelManager::new(length, width, height);
+        let colors = VoxelManager::colors();
+
+        let layers = match self {
+            Scene::Empty => 0..0,
+            Scene::HalfFull => 0..height / 2,
+            Scene::Full => 0..height,
+            Scene::Avalanche => height / 2..height,
+        };
+
+        for x in 0..width {
+            for y in layers.clone() {
+                for z in 0..length {
+                    manager.set_voxel(x, y, z, Some(colors[(x + y + z) % colors.len()]));
+                }
+            }
+        }
+
+        manager
+    }
+}
+
+
+fn size_label((length, width, height): (usize, usize, usize)) -> String {
+    format!("{width}x{height}x{length}")
+}
+
+
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+
+    for size in SIZES {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &manager, |b, manager| {
+                b.iter_batched_ref(|| manager.clone(), |manager| manager.update(), BatchSize::LargeInput)
+            });
+        }
+    }
+
+    group.finish();
+}
+
+
+fn bench_update_packed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_packed");
+
+    for size in SIZES.into_iter().chain([(128, 128, 128)]) {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            let grid = BitGrid::from_voxels(manager.voxels(), manager.length, manager.width, manager.height);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &grid, |b, grid| {
+                b.iter_batched_ref(|| grid.clone(), |grid| grid.update(None), BatchSize::LargeInput)
+            });
+        }
+    }
+
+    // the box size the packed grid is meant to reach, at rest and with everything moving
+    group.sample_size(10);
+    let size = (256, 256, 256);
+    for scene in [Scene::Empty, Scene::Avalanche] {
+        let grid = {
+            let manager = scene.build(size);
+            BitGrid::from_voxels(manager.voxels(), manager.length, manager.width, manager.height)
+        };
+        group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &grid, |b, grid| {
+            b.iter_batched_ref(|| grid.clone(), |grid| grid.update(None), BatchSize::LargeInput)
+        });
+    }
+
+    group.finish();
+}
+
+
+fn bench_mesh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_mesh");
+
+    for size in SIZES {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &manager, |b, manager| {
+                b.iter(|| manager.get_face_mesh())
+            });
+        }
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("get_greedy_mesh");
+
+    for size in SIZES {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &manager, |b, manager| {
+                b.iter(|| manager.get_greedy_mesh())
+            });
+        }
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("get_smooth_mesh");
+
+    for size in SIZES {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &manager, |b, manager| {
+                b.iter(|| manager.get_smooth_mesh())
+            });
+        }
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("get_instances");
+
+    for size in SIZES {
+        for scene in Scene::ALL {
+            let manager = scene.build(size);
+            group.bench_with_input(BenchmarkId::new(scene.name(), size_label(size)), &manager, |b, manager| {
+                b.iter(|| manager.get_instances())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+
+fn bench_raycast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_ghost_mesh");
+
+    for size in SIZES {
+        let manager = Scene::Empty.build(size);
+        let (length, width, height) = size;
+
+        // look almost straight down at the centre of the top face from above the box
+        let center = Vector3::new(width as f32, -(height as f32), length as f32) * VOXEL_WIDTH / 2.0;
+        let pos = center + Vector3::new(0.05, -10.0, 0.05);
+        let dir = (center - pos).normalize();
+
+        group.bench_with_input(BenchmarkId::from_parameter(size_label(size)), &manager, |b, manager| {
+            b.iter(|| manager.get_ghost_mesh(pos, dir))
+        });
+    }
+
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_update, bench_update_packed, bench_mesh, bench_raycast);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! Runs the sand simulation without a window and renders the result on the CPU.
+//!
+//! `cargo run --example render -- out.png [steps] [size]`
+
+use meshview::camera::Camera;
+use meshview::raster::Rasterizer;
+use meshview::voxel_manager::{VoxelManager, VOXEL_WIDTH};
+use nalgebra::Vector3;
+
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "render.png".to_string());
+    let steps: usize = args.next().map(|s| s.parse().expect("steps must be a number")).unwrap_or(60);
+    let size: usize = args.next().map(|s| s.parse().expect("size must be a number")).unwrap_or(32);
+
+    let mut manager = VoxelManager::new(size, size, size);
+    manager.color_variation = 1.0;
+    manager.randomize(0.15);
+    // stops early once nothing moves
+    let ran = (0..steps).take_while(|_| manager.update()).count();
+
+    let half = size as f32 * VOXEL_WIDTH / 2.0;
+    let mut camera = Camera::default();
+    camera.orbit(Vector3::new(half, -half, half), size as f32 * VOXEL_WIDTH * 1.6, 30.0, 30.0);
+
+    let image = Rasterizer::new(640, 480).render(&[manager.get_mesh()], &camera);
+    std::fs::write(&path, image.encode_png()).expect("Cannot write image");
+    println!("Wrote {path} after {ran} of {steps} steps");
+}
@@ -0,0 +1,219 @@
+use egui::Color32;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use crate::voxel_manager::{Changes, Voxels};
+
+
+/// Voxel array kept in sync with a `BitGrid` as it updates, and where its moves are recorded.
+pub struct Mirror<'a> {
+    pub voxels: &'a mut Voxels,
+    pub changes: &'a mut Changes,
+}
+
+impl Mirror<'_> {
+    fn move_voxel(&mut self, (fx, fy, fz): (usize, usize, usize), (tx, ty, tz): (usize, usize, usize), color: Color32) {
+        self.voxels[fx][fy][fz] = None;
+        self.voxels[tx][ty][tz] = Some(color);
+        self.changes.push(fx, fy, fz);
+        self.changes.push(tx, ty, tz);
+    }
+}
+
+
+/// Bit-packed occupancy for the pure sand rule. Each `(x, y)` row along z is a run of u64
+/// words, so straight falls for 64 cells are computed with a couple of bitwise ops.
+/// Colors live in a separate flat array and are moved alongside the bits.
+#[derive(Debug, Clone)]
+pub struct BitGrid {
+    pub width: usize,
+    pub height: usize,
+    pub length: usize,
+    words_per_row: usize,
+    occupancy: Vec<u64>,
+    colors: Vec<Color32>,
+}
+
+impl BitGrid {
+    pub fn new(length: usize, width: usize, height: usize) -> Self {
+        let words_per_row = length.div_ceil(64);
+
+        Self {
+            width,
+            height,
+            length,
+            words_per_row,
+            occupancy: vec![0; width * height * words_per_row],
+            colors: vec![Color32::TRANSPARENT; width * height * length],
+        }
+    }
+
+    pub fn from_voxels(voxels: &Voxels, length: usize, width: usize, height: usize) -> Self {
+        let mut grid = Self::new(length, width, height);
+
+        for (x, column) in voxels.iter().enumerate() {
+            for (y, row) in column.iter().enumerate() {
+                for (z, voxel) in row.iter().enumerate() {
+                    grid.set(x, y, z, *voxel);
+                }
+            }
+        }
+
+        grid
+    }
+
+    pub fn to_voxels(&self) -> Voxels {
+        (0..self.width).map(|x| {
+            (0..self.height).map(|y| {
+                (0..self.length).map(|z| self.get(x, y, z)).collect()
+            }).collect()
+        }).collect()
+    }
+
+    fn row(&self, x: usize, y: usize) -> usize {
+        (x * self.height + y) * self.words_per_row
+    }
+
+    fn cell(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.height + y) * self.length + z
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<Color32> {
+        let word = self.occupancy[self.row(x, y) + z / 64];
+        (word >> (z % 64) & 1 == 1).then(|| self.colors[self.cell(x, y, z)])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Option<Color32>) {
+        let (word, bit) = (self.row(x, y) + z / 64, 1 << (z % 64));
+        let cell = self.cell(x, y, z);
+
+        match voxel {
+            Some(color) => {
+                self.occupancy[word] |= bit;
+                self.colors[cell] = color;
+            },
+            None => self.occupancy[word] &= !bit,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.occupancy.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn move_voxel(&mut self, from: (usize, usize, usize), to: (usize, usize, usize), mirror: &mut Option<Mirror>) {
+        let color = self.colors[self.cell(from.0, from.1, from.2)];
+        self.set(from.0, from.1, from.2, None);
+        self.set(to.0, to.1, to.2, Some(color));
+
+        if let Some(mirror) = mirror {
+            mirror.move_voxel(from, to, color);
+        }
+    }
+
+    /// Occupancy of row `(x, y)` shifted so bit z holds cell `z + dz`. Cells outside the grid read as occupied.
+    fn shifted_row(&self, x: i32, y: usize, dz: i32, out: &mut [u64]) {
+        if x < 0 || x >= self.width as i32 {
+            out.fill(u64::MAX);
+            return;
+        }
+
+        let row = &self.occupancy[self.row(x as usize, y)..][..self.words_per_row];
+        let last = self.words_per_row - 1;
+        // bits past the end of the row are out of bounds too
+        let padding = if self.length.is_multiple_of(64) { 0 } else { !0u64 << (self.length % 64) };
+        let word = |i: usize| if i == last { row[i] | padding } else { row[i] };
+
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = match dz {
+                0 => word(i),
+                1 => word(i) >> 1 | if i < last { word(i + 1) << 63 } else { 1 << 63 },
+                _ => word(i) << 1 | if i > 0 { word(i - 1) >> 63 } else { 1 },
+            };
+        }
+    }
+
+    /// One tick of the sand rule. Layers are processed bottom up like `SandRule`, but all
+    /// straight falls of a row happen at once before the remaining grains try to slide diagonally.
+    /// With `mirror`, every move is also applied to its voxel array and recorded.
+    pub fn update(&mut self, mut mirror: Option<Mirror>) -> bool {
+        let mut changed = false;
+        let words = self.words_per_row;
+        let mut blocked = vec![0u64; words];
+        let mut neighbor = vec![0u64; words];
+
+        for y in 1..self.height {
+            for x in 0..self.width {
+                let (row, below) = (self.row(x, y), self.row(x, y - 1));
+
+                for i in 0..words {
+                    let fall = self.occupancy[row + i] & !self.occupancy[below + i];
+                    if fall == 0 {
+                        continue;
+                    }
+
+                    self.occupancy[row + i] &= !fall;
+                    self.occupancy[below + i] |= fall;
+                    changed = true;
+
+                    let mut bits = fall;
+                    while bits != 0 {
+                        let z = i * 64 + bits.trailing_zeros() as usize;
+                        bits &= bits - 1;
+
+                        let (from, to) = (self.cell(x, y, z), self.cell(x, y - 1, z));
+                        self.colors[to] = self.colors[from];
+                        if let Some(mirror) = mirror.as_mut() {
+                            mirror.move_voxel((x, y, z), (x, y - 1, z), self.colors[to]);
+                        }
+                    }
+                }
+
+                // grains that can't fall and have every diagonal below taken are settled
+                blocked.fill(u64::MAX);
+                for dx in -1..=1 {
+                    for dz in -1..=1 {
+                        if dx == 0 && dz == 0 {
+                            continue;
+                        }
+                        self.shifted_row(x as i32 + dx, y - 1, dz, &mut neighbor);
+                        blocked.iter_mut().zip(neighbor.iter()).for_each(|(b, n)| *b &= n);
+                    }
+                }
+
+                for (i, blocked) in blocked.iter().enumerate() {
+                    let mut bits = self.occupancy[row + i] & !blocked;
+
+                    while bits != 0 {
+                        let z = i * 64 + bits.trailing_zeros() as usize;
+                        bits &= bits - 1;
+
+                        if self.slide(x, y, z, &mut mirror) {
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        changed
+    }
+
+    fn slide(&mut self, x: usize, y: usize, z: usize, mirror: &mut Option<Mirror>) -> bool {
+        let mut offsets: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+        offsets.shuffle(&mut thread_rng());
+
+        for (dx, dz) in offsets {
+            let (tx, tz) = (x as i32 + dx, z as i32 + dz);
+            if tx < 0 || tx >= self.width as i32 || tz < 0 || tz >= self.length as i32 {
+                continue;
+            }
+
+            if self.get(tx as usize, y - 1, tz as usize).is_none() {
+                self.move_voxel((x, y, z), (tx as usize, y - 1, tz as usize), mirror);
+                return true;
+            }
+        }
+
+        false
+    }
+}
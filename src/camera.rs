@@ -1,5 +1,6 @@
-use nalgebra::{Matrix4, Orthographic3, Perspective3, Vector3};
+use nalgebra::{Matrix4, Orthographic3, Perspective3, Rotation3, Vector3};
 
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub pos : Vector3<f32>,
     pub look : Vector3<f32>,
@@ -9,6 +10,19 @@ pub struct Camera {
 }   
 
 
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(
+            Vector3::new(4.0, 10.0, 10.0),
+            Vector3::new(-45.0, 0.0, 0.0), //forward look vector
+            Vector3::new(1.0, 0.0, 0.0), //right look vector
+            45.0,
+            1.0
+        )
+    }
+}
+
+
 impl Camera{
     pub fn new(pos : Vector3<f32>, look : Vector3<f32>, right: Vector3<f32>, fov : f32, aspect_ratio : f32) -> Self {
         Self {
@@ -20,14 +34,17 @@ impl Camera{
         }
     }
 
-    pub fn default() -> Self {
-        Self::new(
-            Vector3::new(4.0, 10.0, 10.0),
-            Vector3::new(-45.0, 0.0, 0.0), //forward look vector
-            Vector3::new(1.0, 0.0, 0.0), //right look vector
-            45.0,
-            1.0
-        )
+    /// Places the camera `r` away from `center`, looking at it. `theta` is the compass angle
+    /// and `phi` the angle above the floor, both in degrees.
+    pub fn orbit(&mut self, center: Vector3<f32>, r: f32, theta: f32, phi: f32) {
+        let (theta, phi) = (theta.to_radians(), (-phi).to_radians());
+
+        let look = -Vector3::new(phi.cos()* theta.cos(), phi.sin(), phi.cos()*theta.sin()).normalize();
+        let right = (Rotation3::new(90.0_f32.to_radians() * Vector3::new(0.0, 1.0, 0.0)) * Vector3::new(look.x, 0.0, look.z)).normalize();
+
+        self.pos = (-look * r) + center;
+        self.right = right;
+        self.look = look;
     }
 
     pub fn get_up_vec(& self) -> Vector3<f32> {
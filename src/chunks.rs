@@ -0,0 +1,126 @@
+use crate::coloring::MAX_COMPACTION;
+use crate::mesh_data::MeshData;
+use crate::voxel_manager::{Changes, MeshMode, VoxelManager, Voxels};
+
+
+pub const CHUNK_SIZE: usize = 16;
+
+
+/// Splits the grid into `CHUNK_SIZE`³ chunks that are meshed separately. Chunks are marked
+/// dirty around the cells `VoxelManager` reports changed.
+#[derive(Debug, Clone)]
+pub struct Chunks {
+    /// Number of chunks along x, y and z.
+    pub counts: [usize; 3],
+    dims: [usize; 3],
+    dirty: Vec<bool>,
+    /// How far below a change voxels can look different, through the compaction tint.
+    reach_below: usize,
+}
+
+impl Chunks {
+    /// Every chunk starts dirty.
+    pub fn new(manager: &VoxelManager) -> Self {
+        let dims = [manager.width, manager.height, manager.length];
+        let counts = dims.map(|d| d.div_ceil(CHUNK_SIZE));
+
+        Self {
+            counts,
+            dims,
+            dirty: vec![true; counts.iter().product()],
+            reach_below: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.dirty.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirty.is_empty()
+    }
+
+    fn index(&self, [cx, cy, cz]: [usize; 3]) -> usize {
+        (cx * self.counts[1] + cy) * self.counts[2] + cz
+    }
+
+    /// Voxel bounds `(min, max)` of a chunk, max exclusive.
+    pub fn bounds(&self, index: usize) -> ([usize; 3], [usize; 3]) {
+        let chunk = [index / (self.counts[1] * self.counts[2]), (index / self.counts[2]) % self.counts[1], index % self.counts[2]];
+        let min = chunk.map(|c| c * CHUNK_SIZE);
+        let max = [0, 1, 2].map(|i| (min[i] + CHUNK_SIZE).min(self.dims[i]));
+        (min, max)
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.fill(true);
+    }
+
+    /// Marks the chunk holding a voxel dirty, along with every chunk the voxel borders,
+    /// since their exposed faces depend on it, and the chunks below whose tint it changes.
+    pub fn mark_dirty(&mut self, x: usize, y: usize, z: usize) {
+        let p = [x, y, z];
+        let reach = [1, self.reach_below, 1];
+        let lo = [0, 1, 2].map(|i| p[i].saturating_sub(reach[i]) / CHUNK_SIZE);
+        let hi = [0, 1, 2].map(|i| (p[i] + 1).min(self.dims[i] - 1) / CHUNK_SIZE);
+
+        for cx in lo[0]..=hi[0] {
+            for cy in lo[1]..=hi[1] {
+                for cz in lo[2]..=hi[2] {
+                    let i = self.index([cx, cy, cz]);
+                    self.dirty[i] = true;
+                }
+            }
+        }
+    }
+
+    fn track(&mut self, manager: &VoxelManager) {
+        // smooth vertices also average the tint of the voxel above theirs
+        let blended = (manager.mesh_mode == MeshMode::Smooth) as usize;
+        self.reach_below = if manager.color_variation > 0.0 { MAX_COMPACTION + blended } else { 1 };
+    }
+
+    /// Marks the chunks around changed cells dirty, or every chunk when the changes weren't listed.
+    pub fn apply(&mut self, changes: &Changes, manager: &VoxelManager) {
+        self.track(manager);
+
+        match changes.cells() {
+            Some(cells) => cells.iter().for_each(|&[x, y, z]| self.mark_dirty(x, y, z)),
+            None => self.mark_all_dirty(),
+        }
+    }
+
+    /// Diffs the grid against `old` and marks the affected chunks dirty. For changes that
+    /// weren't listed, like a script's, when the previous grid is at hand.
+    pub fn diff(&mut self, old: &Voxels, manager: &VoxelManager) {
+        self.track(manager);
+
+        for (x, (column, old_column)) in manager.voxels().iter().zip(old.iter()).enumerate() {
+            for (y, (row, old_row)) in column.iter().zip(old_column.iter()).enumerate() {
+                for (z, (voxel, old)) in row.iter().zip(old_row.iter()).enumerate() {
+                    if voxel != old {
+                        self.mark_dirty(x, y, z);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns fresh mesh data for every dirty chunk.
+    pub fn remesh(&mut self, manager: &VoxelManager) -> Vec<(usize, MeshData)> {
+        let dirty: Vec<usize> = (0..self.len()).filter(|&i| self.dirty[i]).collect();
+        self.dirty.fill(false);
+
+        dirty.into_iter().map(|i| {
+            let (min, max) = self.bounds(i);
+            (i, manager.get_mesh_region(min, max))
+        }).collect()
+    }
+
+    /// Takes the manager's changes and remeshes the chunks they touch, all on this thread.
+    pub fn update(&mut self, manager: &mut VoxelManager) -> Vec<(usize, MeshData)> {
+        let changes = manager.take_changes();
+        self.apply(&changes, manager);
+        self.remesh(manager)
+    }
+}
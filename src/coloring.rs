@@ -0,0 +1,77 @@
+use egui::Color32;
+use nalgebra::Vector3;
+
+
+/// Voxels stacked above a voxel that still add to its compaction. Chunks this far below a
+/// change are remeshed too.
+pub const MAX_COMPACTION: usize = 8;
+
+
+/// Procedural tint a material's voxels get when meshed, on top of their spawn color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorVariation {
+    /// Brightness jitter from 3D value noise, as a fraction of the color.
+    pub noise: f32,
+    /// Size of the noise features in voxels.
+    pub noise_scale: f32,
+    /// Color the material fades towards at the floor.
+    pub floor_color: Color32,
+    /// How far it fades, 0 to 1, at the floor.
+    pub gradient: f32,
+    /// Darkening per voxel stacked directly on top, up to `MAX_COMPACTION`.
+    pub compaction: f32,
+}
+
+impl ColorVariation {
+    pub const NONE: ColorVariation = ColorVariation {
+        noise: 0.0,
+        noise_scale: 1.0,
+        floor_color: Color32::BLACK,
+        gradient: 0.0,
+        compaction: 0.0,
+    };
+
+    /// Tints `color` for the voxel at `p`. `height` is the fraction of the grid height the
+    /// voxel is at, `stacked` the number of voxels on top of it and `strength` scales every term.
+    pub fn apply(&self, color: Color32, p: [usize; 3], height: f32, stacked: usize, strength: f32) -> Color32 {
+        let [r, g, b, a] = color.to_srgba_unmultiplied();
+        let floor = self.floor_color.to_srgba_unmultiplied();
+
+        let fade = (self.gradient * (1.0 - height) * strength).clamp(0.0, 1.0);
+        let noise = value_noise(Vector3::from(p.map(|c| c as f32)) / self.noise_scale.max(f32::EPSILON)) * 2.0 - 1.0;
+        let brightness = 1.0 + self.noise * noise * strength - self.compaction * stacked.min(MAX_COMPACTION) as f32 * strength;
+
+        let [r, g, b] = [(r, floor[0]), (g, floor[1]), (b, floor[2])].map(|(c, f)| {
+            let faded = c as f32 + (f as f32 - c as f32) * fade;
+            (faded * brightness.max(0.0)).round().clamp(0.0, 255.0) as u8
+        });
+
+        Color32::from_rgba_unmultiplied(r, g, b, a)
+    }
+}
+
+
+fn hash(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841) ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 0xffff as f32
+}
+
+/// Smoothly interpolated lattice noise in 0..1, repeatable for the same point.
+pub fn value_noise(p: Vector3<f32>) -> f32 {
+    let base = p.map(f32::floor);
+    let t = (p - base).map(|t| t * t * (3.0 - 2.0 * t));
+    let [x, y, z] = [base.x as i32, base.y as i32, base.z as i32];
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let corner = |dx, dy, dz| hash(x + dx, y + dy, z + dz);
+
+    let face = |dz| lerp(
+        lerp(corner(0, 0, dz), corner(1, 0, dz), t.x),
+        lerp(corner(0, 1, dz), corner(1, 1, dz), t.x),
+        t.y,
+    );
+    lerp(face(0), face(1), t.z)
+}
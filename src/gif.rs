@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+
+/// Minimal animated GIF89a writer. Every frame gets its own 256 color palette, picked by
+/// median cut, and loops forever.
+#[derive(Debug)]
+pub struct GifEncoder {
+    bytes: Vec<u8>,
+    width: u16,
+    height: u16,
+    /// Time each frame is shown, in hundredths of a second.
+    delay: u16,
+}
+
+impl GifEncoder {
+    pub fn new(width: u16, height: u16, delay: u16) -> Self {
+        let mut bytes = Vec::new();
+
+        bytes.extend(b"GIF89a");
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // no global color table, 8 bits of color resolution
+        bytes.extend([0x70, 0, 0]);
+
+        // NETSCAPE2.0 extension, repeating forever
+        bytes.extend([0x21, 0xff, 11]);
+        bytes.extend(b"NETSCAPE2.0");
+        bytes.extend([3, 1, 0, 0, 0]);
+
+        Self {
+            bytes,
+            width,
+            height,
+            delay,
+        }
+    }
+
+    /// Adds a frame of opaque RGBA pixels, top row first.
+    pub fn add_frame(&mut self, pixels: &[u8]) {
+        assert_eq!(pixels.len(), self.width as usize * self.height as usize * 4, "Frame size doesn't match the GIF");
+
+        let (palette, indices) = quantize(pixels);
+
+        // graphic control extension with the frame delay
+        self.bytes.extend([0x21, 0xf9, 4, 0]);
+        self.bytes.extend(self.delay.to_le_bytes());
+        self.bytes.extend([0, 0]);
+
+        // image descriptor covering the whole canvas, with a local 256 color table
+        self.bytes.push(0x2c);
+        self.bytes.extend([0, 0, 0, 0]);
+        self.bytes.extend(self.width.to_le_bytes());
+        self.bytes.extend(self.height.to_le_bytes());
+        self.bytes.push(0x87);
+        for i in 0..256 {
+            self.bytes.extend(palette.get(i).unwrap_or(&[0; 3]));
+        }
+
+        self.bytes.push(8);
+        for block in lzw_encode(&indices, 8).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend(block);
+        }
+        self.bytes.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3b);
+        self.bytes
+    }
+}
+
+
+/// 5 bits per channel, enough to pick a palette from.
+fn reduce(pixel: &[u8]) -> u16 {
+    (pixel[0] as u16 >> 3) << 10 | (pixel[1] as u16 >> 3) << 5 | pixel[2] as u16 >> 3
+}
+
+fn channel(color: u16, c: usize) -> u16 {
+    color >> (10 - 5 * c) & 31
+}
+
+/// Up to 256 palette colors for the pixels by median cut over a 15 bit histogram, and the
+/// palette index of every pixel.
+pub fn quantize(pixels: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut counts = vec![0u32; 1 << 15];
+    for pixel in pixels.chunks_exact(4) {
+        counts[reduce(pixel) as usize] += 1;
+    }
+
+    let colors: Vec<(u16, u32)> = counts.iter().enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(color, &count)| (color as u16, count))
+        .collect();
+
+    let range = |colors: &[(u16, u32)], c: usize| {
+        let values = colors.iter().map(|&(color, _)| channel(color, c));
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+
+    let mut boxes = vec![colors];
+    while boxes.len() < 256 {
+        // split the box with the widest channel at its median pixel
+        let Some((i, c, _)) = boxes.iter().enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| (0..3).map(move |c| (i, c, range(colors, c))))
+            .max_by_key(|&(_, _, range)| range)
+        else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by_key(|&(color, _)| channel(color, c));
+        let half = colors.iter().map(|&(_, count)| count as u64).sum::<u64>() / 2;
+        let mut seen = 0;
+        let split = colors.iter().position(|&(_, count)| {
+            seen += count as u64;
+            seen >= half
+        }).unwrap_or(0).clamp(0, colors.len() - 2) + 1;
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    let mut lookup = vec![0u8; 1 << 15];
+    let palette = boxes.iter().enumerate().map(|(i, colors)| {
+        let total = colors.iter().map(|&(_, count)| count as u64).sum::<u64>().max(1);
+        let mut sum = [0u64; 3];
+        for &(color, count) in colors.iter() {
+            lookup[color as usize] = i as u8;
+            for (c, s) in sum.iter_mut().enumerate() {
+                // center of the 5 bit bucket
+                *s += (channel(color, c) as u64 * 8 + 4) * count as u64;
+            }
+        }
+        sum.map(|s| (s / total).min(255) as u8)
+    }).collect();
+
+    let indices = pixels.chunks_exact(4).map(|pixel| lookup[reduce(pixel) as usize]).collect();
+    (palette, indices)
+}
+
+
+/// Packs variable width codes least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// GIF flavored LZW: codes start one bit wider than `min_code_size`, grow up to 12 bits,
+/// and the table is cleared once it's full.
+pub fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let min_width = min_code_size as u32 + 1;
+
+    let mut writer = BitWriter { bytes: Vec::new(), buffer: 0, bits: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_width;
+
+    writer.write(clear, width);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(end, width);
+        return writer.finish();
+    };
+
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, width);
+        if next < 4096 {
+            table.insert((prefix, index), next);
+            next += 1;
+            // the decoder adds its entry one code later, and widens once it's out of codes
+            if next > 1 << width && width < 12 {
+                width += 1;
+            }
+        } else {
+            writer.write(clear, width);
+            table.clear();
+            next = end + 1;
+            width = min_width;
+        }
+        prefix = index as u16;
+    }
+
+    writer.write(prefix, width);
+    // the decoder adds an entry for the last code too, which can widen the end code
+    if next >= 1 << width && width < 12 {
+        width += 1;
+    }
+    writer.write(end, width);
+
+    writer.finish()
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use eframe::glow::{self, HasContext as _};
+
+
+static LIVE_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of GL objects (buffers, vertex arrays, programs, textures, framebuffers) created by
+/// this crate and not deleted yet. It should stay flat over a session.
+pub fn live_objects() -> usize {
+    LIVE_OBJECTS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn track_created(count: usize) {
+    LIVE_OBJECTS.fetch_add(count, Ordering::Relaxed);
+}
+
+pub(crate) fn track_deleted(count: usize) {
+    LIVE_OBJECTS.fetch_sub(count, Ordering::Relaxed);
+}
+
+
+/// A GL buffer whose storage is reused between uploads. Data that fits is written with
+/// `buffer_sub_data`; anything larger reallocates to the next power of two.
+#[derive(Debug)]
+pub struct GpuBuffer {
+    pub buffer: glow::Buffer,
+    /// Allocated size in bytes.
+    pub capacity: usize,
+}
+
+impl GpuBuffer {
+    pub fn new(gl: &glow::Context) -> Self {
+        let buffer = unsafe { gl.create_buffer().expect("Cannot create buffer") };
+        track_created(1);
+
+        Self {
+            buffer,
+            capacity: 0,
+        }
+    }
+
+    /// Binds the buffer to `target` and replaces its contents with `data`.
+    pub fn upload(&mut self, gl: &glow::Context, target: u32, data: &[u8]) {
+        unsafe {
+            gl.bind_buffer(target, Some(self.buffer));
+
+            if data.len() > self.capacity {
+                self.capacity = data.len().next_power_of_two();
+                gl.buffer_data_size(target, self.capacity as i32, glow::DYNAMIC_DRAW);
+            }
+            if !data.is_empty() {
+                gl.buffer_sub_data_u8_slice(target, 0, data);
+            }
+        }
+    }
+
+    pub fn destroy(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_buffer(self.buffer);
+        }
+        track_deleted(1);
+    }
+}
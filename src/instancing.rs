@@ -0,0 +1,70 @@
+use eframe::glow::{self, HasContext as _};
+
+use crate::gpu::GpuBuffer;
+use crate::mesh::Mesh;
+use crate::mesh_data::{MeshData, VoxelInstance};
+
+
+/// Draws voxels as instances of one cube mesh instead of meshing them. The instance buffer
+/// is refilled with sub-data uploads and only reallocated when it outgrows its capacity.
+#[derive(Debug)]
+pub struct InstancedCubes {
+    pub cube: Mesh,
+    pub instance_buffer: GpuBuffer,
+    /// Number of instances drawn.
+    pub count: usize,
+}
+
+
+impl InstancedCubes {
+    pub fn new(gl: &glow::Context) -> Self {
+        let cube = Mesh::from_data(gl, &MeshData::cube(), false);
+
+        let instance_buffer = GpuBuffer::new(gl);
+
+        unsafe {
+            let stride = VoxelInstance::STRIDE as i32;
+
+            gl.bind_vertex_array(Some(cube.vertex_array));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(instance_buffer.buffer));
+            gl.vertex_attrib_pointer_f32(5, 3, glow::UNSIGNED_SHORT, false, stride, 0);  // Voxel position
+            gl.vertex_attrib_divisor(5, 1);
+            gl.enable_vertex_attrib_array(5);
+            gl.vertex_attrib_pointer_f32(6, 4, glow::UNSIGNED_BYTE, true, stride, 6);  // Voxel color
+            gl.vertex_attrib_divisor(6, 1);
+            gl.enable_vertex_attrib_array(6);
+            gl.bind_vertex_array(None);
+
+            Self {
+                cube,
+                instance_buffer,
+                count: 0,
+            }
+        }
+    }
+
+    /// Replaces the instances. Returns the number of bytes uploaded.
+    pub fn update(&mut self, gl: &glow::Context, instances: &[VoxelInstance]) -> usize {
+        let data = VoxelInstance::encode(instances);
+        self.instance_buffer.upload(gl, glow::ARRAY_BUFFER, &data);
+
+        self.count = instances.len();
+        data.len()
+    }
+
+    pub fn draw(&self, gl: &glow::Context) {
+        if self.count == 0 {
+            return;
+        }
+
+        unsafe {
+            gl.bind_vertex_array(Some(self.cube.vertex_array));
+            gl.draw_elements_instanced(glow::TRIANGLES, self.cube.index_buffer_size as i32, self.cube.index_type, 0, self.count as i32);
+        }
+    }
+
+    pub fn destroy(&self, gl: &glow::Context) {
+        self.cube.destroy(gl);
+        self.instance_buffer.destroy(gl);
+    }
+}
@@ -0,0 +1,29 @@
+pub mod bit_grid;
+pub mod camera;
+pub mod chunks;
+pub mod coloring;
+pub mod gif;
+#[cfg(feature = "gl")]
+pub mod gpu;
+#[cfg(feature = "gl")]
+pub mod instancing;
+pub mod lighting;
+#[cfg(feature = "gl")]
+pub mod mesh;
+pub mod mesh_data;
+pub mod mesher;
+pub mod raster;
+pub mod recording;
+#[cfg(feature = "gl")]
+pub mod render_queue;
+pub mod rules;
+pub mod screenshot;
+pub mod script;
+#[cfg(feature = "gl")]
+pub mod shader;
+pub mod shader_error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shader_watcher;
+#[cfg(feature = "gl")]
+pub mod shadow;
+pub mod voxel_manager;
@@ -0,0 +1,36 @@
+use nalgebra::Vector3;
+
+
+/// Directional light plus a constant ambient term.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lighting {
+    /// Compass angle of the light in degrees.
+    pub azimuth: f32,
+    /// Angle of the light above the floor in degrees.
+    pub elevation: f32,
+    /// Brightness of faces turned away from the light, 0 to 1.
+    pub ambient: f32,
+    /// How much fully occluded corners are darkened, 0 to 1.
+    pub ambient_occlusion: f32,
+    pub shadows: bool,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            azimuth: 30.0,
+            elevation: 60.0,
+            ambient: 0.35,
+            ambient_occlusion: 0.6,
+            shadows: true,
+        }
+    }
+}
+
+impl Lighting {
+    /// Unit vector pointing towards the light, in voxel space.
+    pub fn direction(&self) -> Vector3<f32> {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        Vector3::new(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin())
+    }
+}
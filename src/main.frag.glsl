@@ -3,13 +3,47 @@
 in vec3 fs_pos;
 in vec4 fs_col;
 in vec2 fs_uv;
+in vec3 fs_normal;
+in float fs_occlusion;
+in vec4 fs_light_pos;
 out vec4 frag_color;
 
+// direction towards the light, in voxel space (y up)
+uniform vec3 u_LightDir;
+uniform float u_Ambient;
+// darkening of a fully occluded corner
+uniform float u_AoStrength;
+uniform sampler2DShadow u_ShadowMap;
+uniform bool u_Shadows;
+
+// fraction of a 3x3 neighborhood of shadow map taps that sees the light
+float visibility() {
+    vec3 p = fs_light_pos.xyz / fs_light_pos.w * 0.5 + 0.5;
+    if (!u_Shadows || p.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(u_ShadowMap, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(u_ShadowMap, vec3(p.xy + vec2(x, y) * texel, p.z - 0.0005));
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
     // frag_color = vec4(fs_uv, 0, 1);  // Sample the texture
     // frag_color = vec4(1 - fs_uv, 0, 1);
     // frag_color = vec4(fs_col.xyz, 1.0);
     // frag_color = vec4(1.0, 1.0, 1.0, 1.0);
     // frag_color  = vec4(gl_FragCoord.z);
-    frag_color = fs_col;
+    // geometry without normals (bounding box, ghost) is drawn unlit
+    float light = 1.0;
+    if (dot(fs_normal, fs_normal) > 0.0) {
+        light = u_Ambient + (1.0 - u_Ambient) * max(dot(normalize(fs_normal), u_LightDir), 0.0) * visibility();
+    }
+    light *= 1.0 - u_AoStrength * fs_occlusion / 3.0;
+    frag_color = vec4(fs_col.rgb * light, fs_col.a);
 }
\ No newline at end of file
@@ -3,23 +3,32 @@ use core::f32;
 use std::{ops::RangeInclusive, sync::{Arc, Mutex}};
 
 
-mod mesh;
-mod camera;
-
 use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
-use mesh::Mesh;
-
-use camera::Camera;
-use eframe::{egui::{self, Color32, Rect}, egui_glow};
-use egui::{pos2, vec2, InputState, Margin, ViewportBuilder};
-use nalgebra::{Rotation3, Vector2, Vector3, Vector4, VectorView3};
+use meshview::chunks::Chunks;
+use meshview::mesher::{MeshBatch, Mesher};
+use meshview::gpu;
+use meshview::instancing::InstancedCubes;
+use meshview::mesh::Mesh;
+use meshview::mesh_data::MeshData;
+use meshview::render_queue::{Blend, DrawItem, RenderQueue};
+
+use meshview::camera::Camera;
+use eframe::{egui::{self, Rect}, egui_glow};
+use egui::{pos2, vec2, Margin, ViewportBuilder};
+use nalgebra::{Vector3, Vector4};
 
-mod shader;
 use rand::random;
-use shader::ShaderProgram;
-use voxel_manager::VoxelManager;
-
-mod voxel_manager;
+use meshview::rules::{builtin_rules, LifeRule, SandRule, VoxelRule};
+use meshview::script::{VoxelScript, SAND_SCRIPT};
+use meshview::lighting::Lighting;
+use meshview::shader::ShaderProgram;
+#[cfg(not(target_arch = "wasm32"))]
+use meshview::shader_watcher::ShaderWatcher;
+use meshview::recording::{Recorder, RecordingFormat, RecordingOutput, RecordingSettings};
+use meshview::screenshot::{encode_png, unpremultiply, RenderTarget};
+use meshview::shadow::ShadowMap;
+use meshview::voxel_manager::{self, Material, MeshMode, VoxelManager};
+use web_time::{Duration, Instant};
 
 
 
@@ -97,24 +106,163 @@ fn main() {
 
 struct App {
     voxel_manager: VoxelManager,
-    mesh: Arc<Mutex<Mesh>>,
+    mesher: Mesher,
+    meshes: Arc<Mutex<Vec<Mesh>>>,
+    /// Translucent triangles of each chunk, merged into `translucent` and sorted on the CPU.
+    translucent_parts: Vec<MeshData>,
+    translucent: Arc<Mutex<Mesh>>,
+    /// Eye position `translucent` was last sorted for, `None` when the parts changed since.
+    translucent_eye: Option<Vector3<f32>>,
+    /// What new voxels are made of.
+    material: Material,
     target: Option<(usize, usize)>,
     ghost: Arc<Mutex<Option<Mesh>>>,
     bounding_box: Arc<Mutex<Mesh>>,
     camera: Arc<Mutex<Camera>>,
+    lighting: Arc<Mutex<Lighting>>,
+    shadow_map: Arc<Mutex<ShadowMap>>,
+    instances: Arc<Mutex<InstancedCubes>>,
+    /// Draw voxels as cube instances instead of chunk meshes.
+    instanced: bool,
+    stats: RenderStats,
+    screenshot: ScreenshotSettings,
+    recording_settings: RecordingSettings,
+    /// Running recording and the target its frames are rendered into.
+    recording: Option<(Recorder, RenderTarget)>,
     shader_program: Arc<Mutex<ShaderProgram>>,
-    value: f32,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: ShaderWatcher,
     angle: (f32, f32, f32),
-    speed: f32
+    speed: f32,
+    grid_size: (usize, usize, usize),
+    preserve_on_resize: bool,
+    rules: Vec<Arc<dyn VoxelRule>>,
+    life_notation: String,
+    fill_density: f64,
+    script_source: String,
+    script_error: Option<String>
+}
+
+
+/// Cost of the last rebuild of each render path, shown under Stats.
+#[derive(Default)]
+struct RenderStats {
+    mesh_rebuild: Option<Duration>,
+    instance_rebuild: Option<Duration>,
+    instance_bytes: usize,
+    comparison: Option<Comparison>,
+}
+
+/// Both paths built from the same grid on the CPU, whichever one is active.
+struct Comparison {
+    mesh: Duration,
+    triangles: usize,
+    instances: Duration,
+    cubes: usize,
+}
+
+impl Comparison {
+    fn measure(manager: &VoxelManager) -> Self {
+        let start = Instant::now();
+        let triangles = manager.get_mesh().triangle_count();
+        let mesh = start.elapsed();
+
+        let start = Instant::now();
+        let cubes = manager.get_instances().len();
+        let instances = start.elapsed();
+
+        Self {
+            mesh,
+            triangles,
+            instances,
+            cubes,
+        }
+    }
 }
 
+
+/// Shared handles to everything drawn in the viewport, so it can be painted from the
+/// paint callback and offscreen.
+#[derive(Clone)]
+struct Scene {
+    shader_program: Arc<Mutex<ShaderProgram>>,
+    meshes: Arc<Mutex<Vec<Mesh>>>,
+    translucent: Arc<Mutex<Mesh>>,
+    ghost: Arc<Mutex<Option<Mesh>>>,
+    bounding_box: Arc<Mutex<Mesh>>,
+    lighting: Arc<Mutex<Lighting>>,
+    shadow_map: Arc<Mutex<ShadowMap>>,
+    instances: Arc<Mutex<InstancedCubes>>,
+    instanced: bool,
+}
+
+impl Scene {
+    fn paint(&self, gl: &eframe::glow::Context, camera: &Camera, ghost: bool) {
+        let meshes = self.meshes.lock().unwrap();
+        let translucent = self.translucent.lock().unwrap();
+        let instances = self.instances.lock().unwrap();
+        let ghost_mesh = self.ghost.lock().unwrap();
+        let bounding_box = self.bounding_box.lock().unwrap();
+
+        // the shadow map covers the box, which spans the whole grid
+        let bounds = bounding_box.positions.iter().fold(
+            (Vector3::repeat(f32::MAX), Vector3::repeat(f32::MIN)),
+            |(min, max), p| (min.inf(p), max.sup(p))
+        );
+        let mut queue = RenderQueue::new(bounds);
+        queue.push(DrawItem::mesh(&bounding_box).primitive(eframe::glow::LINES));
+        if let Some(ghost_mesh) = ghost_mesh.as_ref().filter(|_| ghost) {
+            queue.push(DrawItem::mesh(ghost_mesh));
+        }
+        if self.instanced {
+            queue.push(DrawItem::instances(&instances).casts_shadow());
+        } else {
+            for mesh in meshes.iter() {
+                queue.push(DrawItem::mesh(mesh).casts_shadow());
+            }
+            // sorted back to front by `sort_translucent`
+            queue.push(DrawItem::mesh(&translucent).blend(Blend::Alpha));
+        }
+
+        self.shader_program.lock().unwrap().paint(gl, &queue, camera, &self.lighting.lock().unwrap(), &self.shadow_map.lock().unwrap());
+    }
+}
+
+
+/// Resolution and background of saved screenshots.
+#[derive(Clone, Copy)]
+struct ScreenshotSettings {
+    width: u32,
+    height: u32,
+    transparent: bool,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            width: 3840,
+            height: 2160,
+            transparent: false,
+        }
+    }
+}
+
+
+const GRID_SIZE_KEY: &str = "grid_size";
+const SCRIPT_KEY: &str = "script";
+const DEFAULT_GRID_SIZE: (usize, usize, usize) = (50, 30, 50);
+
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         //update mesh
         let update = self.voxel_manager.update();
+        let capture = self.recording.as_mut().is_some_and(|(recorder, _)| recorder.step());
         if update {
-            self.mesh = Arc::new(Mutex::new(self.voxel_manager.get_mesh(_frame.gl().unwrap())));
+            self.remesh(_frame.gl().unwrap(), false);
         }
+        self.upload_meshes(_frame.gl().unwrap());
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_shaders(_frame.gl().unwrap());
         // self.mesh.lock().unwrap().load_buffers(_frame.gl().unwrap());
 
         // raycast
@@ -123,6 +271,11 @@ impl eframe::App for App {
         // let mut ray = view_proj * Vector4::new(0.0, 0.0, 0.0, 1.0);
         // ray = ray / ray.w
 
+        let mut resize_grid = false;
+        let mut remesh = false;
+        let mut save_screenshot = false;
+        let mut toggle_recording = false;
+
         egui::TopBottomPanel::bottom("BottomPanel")
             .frame(egui::Frame { inner_margin: 
                 Margin { 
@@ -141,7 +294,7 @@ impl eframe::App for App {
             // });
 
             ui.collapsing("Help", |ui| {
-                ui.label(format!("Num Verts: {}", self.mesh.lock().unwrap().positions.len()));
+                ui.label(format!("Num Verts: {}", self.meshes.lock().unwrap().iter().map(|mesh| mesh.positions.len()).sum::<usize>()));
 
                 let markdown_text =
                 r"
@@ -152,13 +305,40 @@ impl eframe::App for App {
 ## Voxel Sand Simulation
 A simple 3D version of the [pixel sand simulation](https://www.saahil-gupta.com/sand/) built on the same techniques. Built with OpenGL, Rust, and glow. Find the code on [Github](https://github.com/seabiscuit-iv/voxel-sand-simulation).
 
-Note that 3D cellular automata is very inefficient and does not scale well, especially on WebGL. The dimensions of the box have been set to 50x50x30, but the application will likely slow down when reaching a large number of voxels. For best performance, please use a browser like Chrome or Edge.
+Note that 3D cellular automata is very inefficient and does not scale well, especially on WebGL. The dimensions of the box default to 50x50x30 and can be changed under Grid, but the application will likely slow down when reaching a large number of voxels. For best performance, please use a browser like Chrome or Edge.
 
 Made by [Saahil Gupta](https://www.saahil-gupta.com)   
                 ";
 
                 let mut cache = CommonMarkCache::default();
-                CommonMarkViewer::new().show(ui, &mut cache, &markdown_text);
+                CommonMarkViewer::new().show(ui, &mut cache, markdown_text);
+            });
+            ui.collapsing("Stats", |ui| {
+                let millis = |time: Option<Duration>| time.map_or("-".to_string(), |time| format!("{:.2} ms", time.as_secs_f64() * 1000.0));
+
+                let meshes = self.meshes.lock().unwrap();
+                let (vertices, triangles) = meshes.iter().fold((0, 0), |(v, t), mesh| (v + mesh.positions.len(), t + mesh.indicies.len() / 3));
+                let meshing = if self.mesher.is_busy() { ", meshing" } else { "" };
+                ui.label(format!("Mesh rebuild: {} ({vertices} verts, {triangles} tris{meshing})", millis(self.stats.mesh_rebuild)));
+
+                let instances = self.instances.lock().unwrap();
+                ui.label(format!(
+                    "Instance rebuild: {} ({} cubes, {} KiB uploaded)",
+                    millis(self.stats.instance_rebuild), instances.count, self.stats.instance_bytes / 1024
+                ));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Compare").on_hover_text("Build the whole grid as one mesh and as instances, without uploading").clicked() {
+                        self.stats.comparison = Some(Comparison::measure(&self.voxel_manager));
+                    }
+                    if let Some(comparison) = &self.stats.comparison {
+                        ui.label(format!(
+                            "mesh {} ({} tris), instances {} ({} cubes)",
+                            millis(Some(comparison.mesh)), comparison.triangles, millis(Some(comparison.instances)), comparison.cubes
+                        ));
+                    }
+                });
+                ui.label(format!("Live GL objects: {}", gpu::live_objects()));
             });
             ui.collapsing("Camera Controls", |ui| {
                 ui.horizontal(|ui| {
@@ -169,8 +349,168 @@ Made by [Saahil Gupta](https://www.saahil-gupta.com)
                 ui.label("Speed");
                 ui.add(egui::Slider::new(&mut self.speed, RangeInclusive::new(0.0, 20.0)));
             });
+            ui.collapsing("Visual Properties", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Meshing");
+                    remesh |= ui.radio_value(&mut self.voxel_manager.mesh_mode, MeshMode::Faces, "Faces").changed();
+                    remesh |= ui.radio_value(&mut self.voxel_manager.mesh_mode, MeshMode::Greedy, "Greedy").changed();
+                    remesh |= ui.radio_value(&mut self.voxel_manager.mesh_mode, MeshMode::Smooth, "Smooth").changed();
+                });
+                remesh |= ui.add(egui::Slider::new(&mut self.voxel_manager.color_variation, RangeInclusive::new(0.0, 2.0)).text("Color variation"))
+                    .on_hover_text("Noise, height gradient and compaction tint of each material. Greedy meshes blend it across merged faces").changed();
+                remesh |= ui.checkbox(&mut self.instanced, "Instanced cubes").on_hover_text("Draws every visible voxel as a cube instance instead of meshing").changed();
+
+                let mut lighting = self.lighting.lock().unwrap();
+                ui.label("Light Direction");
+                ui.add(egui::Slider::new(&mut lighting.azimuth, RangeInclusive::new(0.0, 360.0)).text("Azimuth"));
+                ui.add(egui::Slider::new(&mut lighting.elevation, RangeInclusive::new(-90.0, 90.0)).text("Elevation"));
+                ui.label("Ambient");
+                ui.add(egui::Slider::new(&mut lighting.ambient, RangeInclusive::new(0.0, 1.0)));
+                ui.label("Ambient Occlusion");
+                ui.add(egui::Slider::new(&mut lighting.ambient_occlusion, RangeInclusive::new(0.0, 1.0)));
+                ui.checkbox(&mut lighting.shadows, "Shadows");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Material");
+                for material in Material::ALL {
+                    ui.radio_value(&mut self.material, material, material.name());
+                }
+            });
+            ui.collapsing("Screenshot", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Width");
+                    ui.add(egui::DragValue::new(&mut self.screenshot.width).range(RangeInclusive::new(16, 8192)));
+                    ui.label("Height");
+                    ui.add(egui::DragValue::new(&mut self.screenshot.height).range(RangeInclusive::new(16, 8192)));
+                });
+                ui.checkbox(&mut self.screenshot.transparent, "Transparent background");
+                save_screenshot = ui.button("Save screenshot").clicked();
+            });
+            ui.collapsing("Recording", |ui| {
+                let recording = self.recording.is_some();
+                ui.add_enabled_ui(!recording, |ui| {
+                    let settings = &mut self.recording_settings;
+                    ui.horizontal(|ui| {
+                        ui.label("Width");
+                        ui.add(egui::DragValue::new(&mut settings.width).range(RangeInclusive::new(16, 2048)));
+                        ui.label("Height");
+                        ui.add(egui::DragValue::new(&mut settings.height).range(RangeInclusive::new(16, 2048)));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Every");
+                        ui.add(egui::DragValue::new(&mut settings.every).range(RangeInclusive::new(1, 100)).suffix(" ticks"));
+                        ui.label("Playback");
+                        ui.add(egui::DragValue::new(&mut settings.fps).range(RangeInclusive::new(1, 50)).suffix(" fps"));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut settings.format, RecordingFormat::Gif, "GIF");
+                        ui.radio_value(&mut settings.format, RecordingFormat::PngSequence, "PNG sequence");
+                    });
+                    ui.checkbox(&mut settings.lock_tick, "Lock to simulation").on_hover_text("Waits for meshing before every frame, so the recording stays smooth when meshing falls behind");
+                });
+                ui.horizontal(|ui| {
+                    toggle_recording = ui.button(if recording { "Stop" } else { "Start recording" }).clicked();
+                    if let Some((recorder, _)) = &self.recording {
+                        ui.label(format!("{} frames", recorder.frame_count));
+                    }
+                });
+            });
+            ui.collapsing("Grid", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Width");
+                    ui.add(egui::DragValue::new(&mut self.grid_size.0).range(RangeInclusive::new(1, 256)));
+                    ui.label("Height");
+                    ui.add(egui::DragValue::new(&mut self.grid_size.1).range(RangeInclusive::new(1, 256)));
+                    ui.label("Length");
+                    ui.add(egui::DragValue::new(&mut self.grid_size.2).range(RangeInclusive::new(1, 256)));
+                });
+                ui.checkbox(&mut self.preserve_on_resize, "Keep existing sand");
+                resize_grid = ui.button("Apply").clicked();
+            });
+            ui.collapsing("Rules", |ui| {
+                egui::ComboBox::from_label("Rule")
+                    .selected_text(if self.voxel_manager.script.is_some() { "Script".to_string() } else { self.voxel_manager.rule.name() })
+                    .show_ui(ui, |ui| {
+                        for rule in self.rules.iter() {
+                            if ui.selectable_label(self.voxel_manager.script.is_none() && rule.name() == self.voxel_manager.rule.name(), rule.name()).clicked() {
+                                self.voxel_manager.rule = rule.clone();
+                                self.voxel_manager.script = None;
+                                self.voxel_manager.set_packed(false);
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Life");
+                    ui.text_edit_singleline(&mut self.life_notation);
+                    if ui.button("Use").clicked() {
+                        match self.life_notation.parse::<LifeRule>() {
+                            Ok(rule) => {
+                                self.voxel_manager.rule = Arc::new(rule);
+                                self.voxel_manager.script = None;
+                                self.voxel_manager.set_packed(false);
+                                self.script_error = None;
+                            },
+                            Err(err) => self.script_error = Some(err)
+                        }
+                    }
+                });
+                let mut packed = self.voxel_manager.packed().is_some();
+                if ui.checkbox(&mut packed, "Bit-packed sand").on_hover_text("Runs the sand rule on a bit-packed grid, much faster for large boxes").changed() {
+                    if packed {
+                        self.voxel_manager.rule = Arc::new(SandRule);
+                        self.voxel_manager.script = None;
+                    }
+                    self.voxel_manager.set_packed(packed);
+                }
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(&mut self.fill_density, RangeInclusive::new(0.0, 1.0)).text("Density"));
+                    if ui.button("Fill randomly").clicked() {
+                        self.voxel_manager.randomize(self.fill_density);
+                        remesh = true;
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    ui.add(egui::TextEdit::multiline(&mut self.script_source).code_editor().desired_width(f32::INFINITY));
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Run script").clicked() {
+                        match VoxelScript::new(&self.script_source) {
+                            Ok(script) => {
+                                self.voxel_manager.script = Some(Arc::new(script));
+                                self.voxel_manager.set_packed(false);
+                                self.voxel_manager.script_error = None;
+                                self.script_error = None;
+                            },
+                            Err(err) => self.script_error = Some(err.to_string())
+                        }
+                    }
+                    if ui.button("Stop script").clicked() {
+                        self.voxel_manager.script = None;
+                    }
+                    if ui.button("Reset script").clicked() {
+                        self.script_source = SAND_SCRIPT.to_string();
+                    }
+                });
+                for err in self.script_error.iter().chain(self.voxel_manager.script_error.iter()) {
+                    ui.colored_label(egui::Color32::RED, err);
+                }
+            });
         });
 
+        if save_screenshot {
+            self.save_screenshot(_frame.gl().unwrap(), ctx);
+        }
+        if toggle_recording {
+            self.toggle_recording(_frame.gl().unwrap());
+        }
+
+        if resize_grid {
+            self.resize_grid(_frame.gl().unwrap());
+        } else if remesh {
+            self.remesh(_frame.gl().unwrap(), true);
+        }
+
         let mut rect: Rect = Rect::from_pos(pos2(0.0, 0.0));
         egui::CentralPanel::default().show(ctx, |ui| {
             let _bounds = egui::Frame::canvas(ui.style()).show(ui, |ui| {
@@ -179,80 +519,97 @@ Made by [Saahil Gupta](https://www.saahil-gupta.com)
             });
         });
 
+        let shader_errors = self.shader_errors();
+        if !shader_errors.is_empty() {
+            egui::Area::new(egui::Id::new("shader_error"))
+                .fixed_pos(rect.left_top() + vec2(8.0, 8.0))
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.colored_label(egui::Color32::RED, egui::RichText::new(shader_errors.join("\n")).monospace());
+                    });
+                });
+        }
+
 
         if ctx.input(|i| i.pointer.button_down(egui::PointerButton::Primary) && rect.contains(i.pointer.latest_pos().unwrap()) && !i.modifiers.alt && !i.modifiers.shift) {
             // println!("Space");
-            match self.target {
-                Some((x, z)) => {
-                    for dx in -2_i32..=2_i32 {
-                        for dz in -2_i32..=2_i32 {
-                            if dx.abs() == 2 && dz.abs() == 2 {
-                                continue;
-                            }
-
-                            let tgt = (x as i32 + dx, 29, z as i32 + dz);
+            if let Some((x, z)) = self.target {
+                let colors = self.material.colors();
+                for dx in -2_i32..=2_i32 {
+                    for dz in -2_i32..=2_i32 {
+                        if dx.abs() == 2 && dz.abs() == 2 {
+                            continue;
+                        }
 
-                            if tgt.0 < 0 || tgt.0 >= 50 || tgt.2 < 0 || tgt.2 >= 50 {
-                                continue;
-                            }
+                        let tgt = (x as i32 + dx, self.voxel_manager.spawn_layer(), z as i32 + dz);
 
-                            self.voxel_manager.voxels[tgt.0 as usize][29][tgt.2 as usize] = Some(VoxelManager::colors()[random::<usize>() % VoxelManager::colors().len()]);
+                        if tgt.0 < 0 || tgt.0 >= self.voxel_manager.width as i32 || tgt.2 < 0 || tgt.2 >= self.voxel_manager.length as i32 {
+                            continue;
                         }
-                    }
 
-                },
-                None => ()
+                        self.voxel_manager.set_voxel(tgt.0 as usize, tgt.1, tgt.2 as usize, Some(colors[random::<usize>() % colors.len()]));
+                    }
+                }
+                self.remesh(_frame.gl().unwrap(), false);
             }
         }
 
         // if ctx.input(|i| i.pointer.button_clicked(egui::PointerButton::Primary) && rect.contains(i.pointer.latest_pos().unwrap())) {
 
-        match ctx.pointer_latest_pos() {
-            Some(norms) => {
-                let norms = (((norms.x - rect.left_top().x) / rect.width()) * 2.0 - 1.0, -(((norms.y - rect.left_top().y) / rect.height()) * 2.0 - 1.0));
-                
-                // println!("{:?}", norms);
-
-                let view_proj_inv = self.camera.lock().unwrap().get_proj_view_mat_inv();
-                let mut ray = view_proj_inv * Vector4::new(norms.0, norms.1, 0.0, 1.0);
-                ray = ray / ray.w;
-                let dir = (Vector3::new(ray.x, ray.y, ray.z) - self.camera.lock().unwrap().pos).normalize();
-
-                
-                if ctx.input(|i| i.key_pressed(egui::Key::A)) {
-                    println!("{}", dir);
-                } 
+        if let Some(norms) = ctx.pointer_latest_pos() {
+            let norms = (((norms.x - rect.left_top().x) / rect.width()) * 2.0 - 1.0, -(((norms.y - rect.left_top().y) / rect.height()) * 2.0 - 1.0));
+            
+            // println!("{:?}", norms);
 
-                let temp = self.voxel_manager.get_ghost_mesh(_frame.gl().unwrap(), self.camera.lock().unwrap().pos, dir);
-                self.target = temp.1.clone();
+            let view_proj_inv = self.camera.lock().unwrap().get_proj_view_mat_inv();
+            let mut ray = view_proj_inv * Vector4::new(norms.0, norms.1, 0.0, 1.0);
+            ray = ray / ray.w;
+            let dir = (Vector3::new(ray.x, ray.y, ray.z) - self.camera.lock().unwrap().pos).normalize();
 
-                self.ghost = Arc::new(Mutex::new(temp.0.clone()))
+            let (ghost, target) = self.voxel_manager.get_ghost_mesh(self.camera.lock().unwrap().pos, dir);
+            self.target = target;
 
-            },
-            None => ()
+            self.set_ghost(_frame.gl().unwrap(), ghost);
         }
 
         // let hit = self.voxel_manager.ray_box_intersection(self.camera.lock().unwrap().pos, ray);
         // }
         
 
-        let (r, mut theta, mut phi) = self.angle;
-        phi = -phi;
-        phi = phi.to_radians();
-        theta = theta.to_radians();
-
-        let look = -Vector3::new(phi.cos()* theta.cos(), phi.sin(), phi.cos()*theta.sin()).normalize();
-        let right = (Rotation3::new(90.0_f32.to_radians() * Vector3::new(0.0, 1.0, 0.0)) * Vector3::new(look.x, 0.0, look.z)).normalize();
-
-        // let look = rot * Vector3::new(0.0, 0.0, 1.0);
-        // let right = rot * Vector3::new(1.0, 0.0, 0.0);
-        self.camera.lock().unwrap().pos = (-look * r) + Vector3::new((self.voxel_manager.width as f32 * voxel_manager::VOXEL_WIDTH) / 2.0, -(self.voxel_manager.height as f32 * voxel_manager::VOXEL_WIDTH / 2.0), (self.voxel_manager.length as f32 * voxel_manager::VOXEL_WIDTH / 2.0));
-        // self.camera.lock().unwrap().pos = -look * r;
-        self.camera.lock().unwrap().right = right;
-        self.camera.lock().unwrap().look = look;
+        let (r, theta, phi) = self.angle;
+        let center = Vector3::new((self.voxel_manager.width as f32 * voxel_manager::VOXEL_WIDTH) / 2.0, -(self.voxel_manager.height as f32 * voxel_manager::VOXEL_WIDTH / 2.0), self.voxel_manager.length as f32 * voxel_manager::VOXEL_WIDTH / 2.0);
+        self.camera.lock().unwrap().orbit(center, r, theta, phi);
+        self.sort_translucent(_frame.gl().unwrap());
+        if capture {
+            self.capture_frame(_frame.gl().unwrap(), ctx);
+        }
         
         ctx.request_repaint();
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, GRID_SIZE_KEY, &self.grid_size);
+        eframe::set_value(storage, SCRIPT_KEY, &self.script_source);
+    }
+
+    fn on_exit(&mut self, gl: Option<&eframe::glow::Context>) {
+        let Some(gl) = gl else {
+            return;
+        };
+
+        for mesh in self.meshes.lock().unwrap().drain(..) {
+            mesh.destroy(gl);
+        }
+        self.translucent.lock().unwrap().destroy(gl);
+        self.set_ghost(gl, None);
+        self.bounding_box.lock().unwrap().destroy(gl);
+        self.instances.lock().unwrap().destroy(gl);
+        self.shadow_map.lock().unwrap().destroy(gl);
+        self.shader_program.lock().unwrap().destroy(gl);
+        if let Some((_, target)) = self.recording.take() {
+            target.destroy(gl);
+        }
+    }
 }
 
 
@@ -263,25 +620,254 @@ impl App {
             .as_ref()
             .expect("You need to run eframe with the glow backend");
 
-        let voxel_manager = VoxelManager::new(50, 50, 30);
-        let mesh = voxel_manager.get_mesh(gl);
-        let bounding_box = voxel_manager.get_bounding_box(gl);
+        let grid_size = cc.storage
+            .and_then(|storage| eframe::get_value(storage, GRID_SIZE_KEY))
+            .unwrap_or(DEFAULT_GRID_SIZE);
+        let script_source = cc.storage
+            .and_then(|storage| eframe::get_value(storage, SCRIPT_KEY))
+            .unwrap_or_else(|| SAND_SCRIPT.to_string());
+
+        let (width, height, length) = grid_size;
+        let mut voxel_manager = VoxelManager::new(length, width, height);
+        voxel_manager.color_variation = 1.0;
+        let mut chunks = Chunks::new(&voxel_manager);
+        let (opaque, translucent_parts): (Vec<_>, Vec<_>) = chunks.remesh(&voxel_manager).into_iter().map(|(_, data)| data.split_translucent()).unzip();
+        let meshes = opaque.iter().map(|data| Mesh::from_data(gl, data, false)).collect();
+        let bounding_box = Mesh::from_data(gl, &voxel_manager.get_bounding_box(), false);
+        let mesher = Mesher::new(chunks, &mut voxel_manager);
 
         let shader_program = ShaderProgram::new(gl, "src/main.vert.glsl", "src/main.frag.glsl");
+        let shadow_map = ShadowMap::new(gl);
+        #[cfg(not(target_arch = "wasm32"))]
+        let shader_watcher = ShaderWatcher::new(shader_program.paths().into_iter().chain(shadow_map.shader.paths()));
         
         let camera = Camera::default();
         
         Self { 
             voxel_manager, 
-            mesh: Arc::new(Mutex::new(mesh)),
+            mesher,
+            meshes: Arc::new(Mutex::new(meshes)),
+            translucent_parts,
+            translucent: Arc::new(Mutex::new(Mesh::from_data(gl, &MeshData::default(), false))),
+            translucent_eye: None,
+            material: Material::default(),
             target: None,
             ghost: Arc::new(Mutex::new(None)),
             bounding_box: Arc::new(Mutex::new(bounding_box)),
             shader_program: Arc::new(Mutex::new(shader_program)),
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher,
             camera: Arc::new(Mutex::new(camera)),
-            value: 0.0,
+            lighting: Arc::new(Mutex::new(Lighting::default())),
+            shadow_map: Arc::new(Mutex::new(shadow_map)),
+            instances: Arc::new(Mutex::new(InstancedCubes::new(gl))),
+            instanced: false,
+            stats: RenderStats::default(),
+            screenshot: ScreenshotSettings::default(),
+            recording_settings: RecordingSettings::default(),
+            recording: None,
             angle: (15.0, 0.0, 15.0),
-            speed: 3.0
+            speed: 3.0,
+            grid_size,
+            preserve_on_resize: true,
+            rules: builtin_rules(),
+            life_notation: "4555".to_string(),
+            fill_density: 0.2,
+            script_source,
+            script_error: None
+        }
+    }
+
+    fn resize_grid(&mut self, gl: &eframe::glow::Context) {
+        let (width, height, length) = self.grid_size;
+        self.voxel_manager.resize(length, width, height, self.preserve_on_resize);
+
+        // the new grid is meshed up front so the chunk meshes line up with the new chunks
+        let mut chunks = Chunks::new(&self.voxel_manager);
+        let mut meshes = self.meshes.lock().unwrap();
+        for mesh in meshes.drain(..) {
+            mesh.destroy(gl);
+        }
+        let (opaque, translucent_parts): (Vec<_>, Vec<_>) = chunks.remesh(&self.voxel_manager).into_iter().map(|(_, data)| data.split_translucent()).unzip();
+        meshes.extend(opaque.iter().map(|data| Mesh::from_data(gl, data, false)));
+        drop(meshes);
+        self.translucent_parts = translucent_parts;
+        self.translucent_eye = None;
+
+        self.mesher = Mesher::new(chunks, &mut self.voxel_manager);
+        self.bounding_box.lock().unwrap().update(gl, &self.voxel_manager.get_bounding_box());
+        self.set_ghost(gl, None);
+        self.target = None;
+
+        if self.instanced {
+            self.remesh(gl, true);
+        }
+    }
+
+    /// Schedules remeshing the chunks that changed since the last remesh, or every chunk with
+    /// `all`. When drawing instanced, every instance is rebuilt right away instead.
+    fn remesh(&mut self, gl: &eframe::glow::Context, all: bool) {
+        if self.instanced {
+            let start = Instant::now();
+            let instances = self.voxel_manager.get_instances();
+            self.stats.instance_bytes = self.instances.lock().unwrap().update(gl, &instances);
+            self.stats.instance_rebuild = Some(start.elapsed());
+            return;
+        }
+
+        self.mesher.request(&mut self.voxel_manager, all);
+    }
+
+    /// Swaps in chunk meshes the mesher has finished. Until then the previous meshes keep drawing.
+    fn upload_meshes(&mut self, gl: &eframe::glow::Context) {
+        if let Some(batch) = self.mesher.poll(&mut self.voxel_manager) {
+            self.apply_batch(gl, batch);
+        }
+    }
+
+    fn apply_batch(&mut self, gl: &eframe::glow::Context, batch: MeshBatch) {
+        let mut meshes = self.meshes.lock().unwrap();
+        for (i, data) in batch.chunks {
+            let (opaque, translucent) = data.split_translucent();
+            meshes[i].update(gl, &opaque);
+            if translucent != self.translucent_parts[i] {
+                self.translucent_parts[i] = translucent;
+                self.translucent_eye = None;
+            }
+        }
+        self.stats.mesh_rebuild = Some(batch.elapsed);
+    }
+
+    /// Re-sorts the translucent triangles back to front when the camera moved or they changed.
+    fn sort_translucent(&mut self, gl: &eframe::glow::Context) {
+        let pos = self.camera.lock().unwrap().pos;
+        let eye = Vector3::new(pos.x, -pos.y, pos.z) / voxel_manager::VOXEL_WIDTH;
+        if self.translucent_eye == Some(eye) {
+            return;
+        }
+
+        let mut data = MeshData::default();
+        for part in self.translucent_parts.iter() {
+            data.append(part);
+        }
+        data.sort_back_to_front(eye);
+
+        self.translucent.lock().unwrap().update(gl, &data);
+        self.translucent_eye = Some(eye);
+    }
+
+    /// Rebuilds the shader programs when their files change. A program that fails to build
+    /// keeps its last working version, and its error is shown until a build succeeds.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self, gl: &eframe::glow::Context) {
+        if !self.shader_watcher.poll() {
+            return;
+        }
+
+        for result in [self.shader_program.lock().unwrap().reload(gl), self.shadow_map.lock().unwrap().shader.reload(gl)] {
+            if let Err(err) = result {
+                log::error!("{err}");
+            }
+        }
+    }
+
+    /// Build errors of the shader programs, one per failing program.
+    fn shader_errors(&self) -> Vec<String> {
+        [&self.shader_program.lock().unwrap().error, &self.shadow_map.lock().unwrap().shader.error]
+            .into_iter()
+            .flatten()
+            .map(|err| err.to_string())
+            .collect()
+    }
+
+    /// Shows the ghost cube, reusing its GL objects while it stays visible.
+    fn set_ghost(&mut self, gl: &eframe::glow::Context, data: Option<MeshData>) {
+        let mut ghost = self.ghost.lock().unwrap();
+
+        match (ghost.as_mut(), data) {
+            (Some(mesh), Some(data)) => mesh.update(gl, &data),
+            (None, Some(data)) => *ghost = Some(Mesh::from_data(gl, &data, false)),
+            (_, None) => {
+                if let Some(mesh) = ghost.take() {
+                    mesh.destroy(gl);
+                }
+            },
+        }
+    }
+
+    fn scene(&self) -> Scene {
+        Scene {
+            shader_program: self.shader_program.clone(),
+            meshes: self.meshes.clone(),
+            translucent: self.translucent.clone(),
+            ghost: self.ghost.clone(),
+            bounding_box: self.bounding_box.clone(),
+            lighting: self.lighting.clone(),
+            shadow_map: self.shadow_map.clone(),
+            instances: self.instances.clone(),
+            instanced: self.instanced,
+        }
+    }
+
+    /// Renders the current view offscreen at the screenshot resolution, without the ghost
+    /// cube, and saves it as a PNG.
+    fn save_screenshot(&mut self, gl: &eframe::glow::Context, ctx: &egui::Context) {
+        let settings = self.screenshot;
+        let target = RenderTarget::new(gl, settings.width as i32, settings.height as i32);
+
+        let mut pixels = self.render_offscreen(gl, ctx, &target, settings.transparent);
+        let (width, height) = (target.width as u32, target.height as u32);
+        target.destroy(gl);
+
+        unpremultiply(&mut pixels);
+        save_file("screenshot.png", ("PNG", "png"), &encode_png(width, height, &pixels));
+    }
+
+    /// Renders the current view into `target`, without the ghost cube, and returns its pixels.
+    fn render_offscreen(&self, gl: &eframe::glow::Context, ctx: &egui::Context, target: &RenderTarget, transparent: bool) -> Vec<u8> {
+        let mut camera = self.camera.lock().unwrap().clone();
+        camera.aspect_ratio = target.width as f32 / target.height as f32;
+
+        // the canvas background, as the color bytes it's stored with
+        let background = if transparent { [0.0; 4] } else { ctx.style().visuals.extreme_bg_color.to_array().map(|c| c as f32 / 255.0) };
+        let scene = self.scene();
+        target.capture(gl, background, |gl| scene.paint(gl, &camera, false))
+    }
+
+    fn toggle_recording(&mut self, gl: &eframe::glow::Context) {
+        let Some((recorder, target)) = self.recording.take() else {
+            let settings = self.recording_settings;
+            let target = RenderTarget::new(gl, settings.width as i32, settings.height as i32);
+            // the driver may not support the full size
+            let settings = RecordingSettings { width: target.width as u32, height: target.height as u32, ..settings };
+            self.recording = Some((Recorder::new(settings), target));
+            return;
+        };
+
+        target.destroy(gl);
+        match recorder.finish() {
+            RecordingOutput::Gif(bytes) => save_file("recording.gif", ("GIF", "gif"), &bytes),
+            RecordingOutput::PngSequence(files) => save_sequence("frame", "png", &files),
+        }
+    }
+
+    /// Adds the current view to the recording. When locked to the simulation, meshing is
+    /// finished first so the frame shows the latest step.
+    fn capture_frame(&mut self, gl: &eframe::glow::Context, ctx: &egui::Context) {
+        let lock_tick = self.recording.as_ref().is_some_and(|(recorder, _)| recorder.settings.lock_tick);
+        if lock_tick {
+            if let Some(batch) = self.mesher.finish(&mut self.voxel_manager) {
+                self.apply_batch(gl, batch);
+            }
+            self.sort_translucent(gl);
+        }
+
+        let Some((_, target)) = &self.recording else {
+            return;
+        };
+        let pixels = self.render_offscreen(gl, ctx, target, false);
+        if let Some((recorder, _)) = self.recording.as_mut() {
+            recorder.add_frame(&pixels);
         }
     }
 
@@ -294,10 +880,7 @@ impl App {
         self.camera.lock().unwrap().aspect_ratio = w / (h);
 
 
-        let shader_program = self.shader_program.clone();
-        let mesh = self.mesh.clone();
-        let ghost = self.ghost.clone();
-        let bounding_box = self.bounding_box.clone();
+        let scene = self.scene();
         let camera = self.camera.clone();
 
         if ui.ctx().input(|i| i.modifiers.shift || i.modifiers.alt) {     
@@ -311,14 +894,82 @@ impl App {
         }
 
 
-        let value = self.value;
-
         let callback = egui::PaintCallback {
             rect,
             callback: std::sync::Arc::new(egui_glow::CallbackFn::new(move |_info: egui::PaintCallbackInfo, painter| {
-                shader_program.lock().unwrap().paint(painter.gl(), &mesh.lock().unwrap(), &ghost.lock().unwrap(), &bounding_box.lock().unwrap(),  &camera.lock().unwrap());
+                scene.paint(painter.gl(), &camera.lock().unwrap(), true);
             })),
         };
         ui.painter().add(callback);
     }
-}
\ No newline at end of file
+}
+
+
+/// Asks where to save `bytes` and writes them there.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_file(name: &str, (filter, extension): (&str, &str), bytes: &[u8]) {
+    let Some(path) = rfd::FileDialog::new().add_filter(filter, &[extension]).set_file_name(name).save_file() else {
+        return;
+    };
+
+    if let Err(err) = std::fs::write(&path, bytes) {
+        log::error!("Failed to save {}: {err}", path.display());
+    }
+}
+
+/// Asks for a folder and writes the files there as `{prefix}_00000.{extension}`, ...
+#[cfg(not(target_arch = "wasm32"))]
+fn save_sequence(prefix: &str, extension: &str, files: &[Vec<u8>]) {
+    let Some(folder) = rfd::FileDialog::new().pick_folder() else {
+        return;
+    };
+
+    for (i, bytes) in files.iter().enumerate() {
+        let path = folder.join(format!("{prefix}_{i:05}.{extension}"));
+        if let Err(err) = std::fs::write(&path, bytes) {
+            log::error!("Failed to save {}: {err}", path.display());
+            return;
+        }
+    }
+}
+
+/// Downloads the files one by one as `{prefix}_00000.{extension}`, ...
+#[cfg(target_arch = "wasm32")]
+fn save_sequence(prefix: &str, extension: &str, files: &[Vec<u8>]) {
+    for (i, bytes) in files.iter().enumerate() {
+        save_file(&format!("{prefix}_{i:05}.{extension}"), (extension, extension), bytes);
+    }
+}
+
+/// Downloads `bytes` as a file through a temporary link.
+#[cfg(target_arch = "wasm32")]
+fn save_file(name: &str, _filter: (&str, &str), bytes: &[u8]) {
+    use eframe::wasm_bindgen::JsCast as _;
+
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = web_sys::Blob::new_with_u8_array_sequence(&parts).expect("Cannot create blob");
+    let url = web_sys::Url::create_object_url_with_blob(&blob).expect("Cannot create object URL");
+
+    let window = web_sys::window().expect("No window");
+    let document = window.document().expect("No document");
+    let link = document
+        .create_element("a")
+        .expect("Cannot create link")
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .expect("Link is not an anchor");
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+
+    // the download only starts after this returns, so the URL has to outlive the click
+    let revoke = eframe::wasm_bindgen::closure::Closure::once_into_js(move || {
+        web_sys::Url::revoke_object_url(&url).ok();
+    });
+    window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), REVOKE_DELAY_MS)
+        .expect("Cannot schedule revoking the object URL");
+}
+
+/// How long a downloaded file's object URL is kept, in milliseconds.
+#[cfg(target_arch = "wasm32")]
+const REVOKE_DELAY_MS: i32 = 10_000;
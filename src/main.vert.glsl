@@ -3,20 +3,42 @@
 layout(location = 0) in vec4 vs_pos;
 layout(location = 1) in vec4 vs_col;
 layout(location = 2) in vec2 vs_uv;
+layout(location = 3) in vec3 vs_normal;
+layout(location = 4) in float vs_occlusion;
+layout(location = 5) in vec3 vs_offset;
+layout(location = 6) in vec4 vs_instance_col;
 
 out vec4 fs_col;
 out vec2 fs_uv; 
 out vec3 fs_pos;
+out vec3 fs_normal;
+out float fs_occlusion;
+out vec4 fs_light_pos;
 
 uniform mat4 u_ViewProj;
+uniform float u_VoxelWidth;
+uniform mat4 u_LightViewProj;
+// instances of a unit cube, placed and colored per instance
+uniform bool u_Instanced;
+// per draw, in voxel units
+uniform mat4 u_Model;
+uniform vec4 u_Tint;
 
 void main() {
     // fs_col = vs_col;
-    fs_col = vs_col;
+    fs_col = (u_Instanced ? vs_instance_col : vs_col) * u_Tint;
     fs_uv = vs_uv;
+    fs_normal = vs_normal;
+    fs_occlusion = vs_occlusion;
 
     vec4 pos = vs_pos;
+    if (u_Instanced) {
+        pos.xyz += vs_offset;
+    }
+    pos = u_Model * pos;
+    pos.xyz *= u_VoxelWidth;
     pos.y *= -1.0;
+    fs_light_pos = u_LightViewProj * pos;
 
     pos =  u_ViewProj * pos;
     // pos.z = 0;
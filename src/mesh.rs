@@ -1,127 +1,211 @@
 use eframe::glow::{self, HasContext as _};
 use egui::Color32;
-use nalgebra::{Vector2, Vector3, Vector4};
-use rand::random;
+use nalgebra::{Vector2, Vector3};
 
+use crate::gpu::{self, GpuBuffer};
+use crate::mesh_data::MeshData;
 
 
-#[derive(Debug, Clone)]
+/// Storage type of a vertex attribute on the GPU. Integer types are converted to float
+/// (not normalized) when read by the shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttribType {
+    U8,
+    U16,
+    F32,
+}
+
+impl AttribType {
+    /// Smallest type that stores every value exactly.
+    pub fn smallest_for(values: impl Iterator<Item = f32>) -> Self {
+        let mut smallest = AttribType::U8;
+
+        for value in values {
+            if value.fract() != 0.0 || !(0.0..=u16::MAX as f32).contains(&value) {
+                return AttribType::F32;
+            }
+            if value > u8::MAX as f32 {
+                smallest = AttribType::U16;
+            }
+        }
+
+        smallest
+    }
+
+    pub fn gl_type(&self) -> u32 {
+        match self {
+            AttribType::U8 => glow::UNSIGNED_BYTE,
+            AttribType::U16 => glow::UNSIGNED_SHORT,
+            AttribType::F32 => glow::FLOAT,
+        }
+    }
+
+    pub fn encode(&self, values: impl Iterator<Item = f32>) -> Vec<u8> {
+        match self {
+            AttribType::U8 => values.map(|x| x as u8).collect(),
+            AttribType::U16 => bytemuck::cast_slice(&values.map(|x| x as u16).collect::<Vec<u16>>()).to_vec(),
+            AttribType::F32 => bytemuck::cast_slice(&values.collect::<Vec<f32>>()).to_vec(),
+        }
+    }
+}
+
+
+
+/// Mesh data uploaded to the GPU. Owns its buffers and vertex array: reuse it with `update`
+/// and call `destroy` before dropping it.
+#[derive(Debug)]
 pub struct Mesh {
     pub positions: Vec<Vector3<f32>>,
     pub indicies : Vec<u32>,
     uvs: Vec<Vector2<f32>>,
-    colors: Vec<Vector4<f32>>,
+    colors: Vec<Color32>,
+    normals: Vec<Vector3<f32>>,
+    occlusion: Vec<u8>,
     pub vertex_array: glow::VertexArray,
-    pub position_buffer: glow::Buffer,
-    pub color_buffer: glow::Buffer,
-    pub index_buffer: glow::Buffer,
-    pub uv_buffer: glow::Buffer,
+    pub position_buffer: GpuBuffer,
+    pub color_buffer: GpuBuffer,
+    pub index_buffer: GpuBuffer,
+    pub uv_buffer: GpuBuffer,
+    pub normal_buffer: GpuBuffer,
+    pub occlusion_buffer: GpuBuffer,
     pub index_buffer_size: u32,
+    /// `glow::UNSIGNED_SHORT` when every index fits, `glow::UNSIGNED_INT` otherwise.
+    pub index_type: u32,
+    pub position_type: AttribType,
     pub wireframe: bool
 }
 
 
 impl Mesh {
-    pub fn new(gl: &glow::Context, positions: Vec<Vector3<f32>>, indicies: Vec<u32>, uvs: Vec<Vector2<f32>>, wireframe: bool, colors: Vec<Color32>) -> Self {
-        use glow::HasContext as _;
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(gl: &glow::Context, positions: Vec<Vector3<f32>>, indicies: Vec<u32>, uvs: Vec<Vector2<f32>>, wireframe: bool, colors: Vec<Color32>, normals: Vec<Vector3<f32>>, occlusion: Vec<u8>) -> Self {
+        let vertex_array = unsafe { gl.create_vertex_array().expect("Cannot create vertex array") };
+        gpu::track_created(1);
+
+        let index_buffer_size = (if wireframe {2} else {1})*indicies.len() as u32;
+
+        let mut x = Self {
+            positions,
+            indicies,
+            uvs,
+            colors,
+            normals,
+            occlusion,
+            vertex_array,
+            position_buffer: GpuBuffer::new(gl),
+            color_buffer: GpuBuffer::new(gl),
+            index_buffer: GpuBuffer::new(gl),
+            uv_buffer: GpuBuffer::new(gl),
+            normal_buffer: GpuBuffer::new(gl),
+            occlusion_buffer: GpuBuffer::new(gl),
+            index_buffer_size,
+            index_type: glow::UNSIGNED_INT,
+            position_type: AttribType::F32,
+            wireframe
+        };
+
+        x.load_buffers(gl);
+
+        x
+    }
 
-        unsafe {
-            let vert_count = positions.len();
-
-            let mut uvs = uvs.clone();
-            
-            let mut colors: Vec<Vector4<f32>> = colors.iter().map(|x| Vector4::new(x.r() as f32 / 255.0, x.g() as f32 / 255.0, x.b() as f32 / 255.0, 1.0)).collect();
-
-            let position_buffer = gl.create_buffer().expect("Cannot create position buffer");
-            let color_buffer = gl.create_buffer().expect("Cannot create color buffer");
-            let uv_buffer = gl.create_buffer().expect("Cannot create uv buffer");
-            let index_buffer = gl.create_buffer().expect("Cannot create index buffer");
-
-            let vertex_array = gl.create_vertex_array().expect("Cannot create vertex array");
-
-            let mut x = Self {
-                positions: positions.clone(), 
-                indicies: indicies.clone(),
-                uvs: uvs.clone(),
-                colors: colors.clone(),
-                vertex_array,
-                position_buffer,
-                color_buffer,
-                index_buffer,
-                uv_buffer,
-                index_buffer_size: (if wireframe {2} else {1})*indicies.len() as u32,
-                wireframe
-            };
-
-            x.load_buffers(gl);
-
-            x
-        }
+    /// Uploads CPU mesh data produced by the simulation.
+    pub fn from_data(gl: &glow::Context, data: &MeshData, wireframe: bool) -> Self {
+        Self::new(gl, data.positions.clone(), data.indicies.clone(), data.uvs.clone(), wireframe, data.colors.clone(), data.normals.clone(), data.occlusion.clone())
     }
 
+    /// Replaces the contents, reusing the existing GL objects.
+    pub fn update(&mut self, gl: &glow::Context, data: &MeshData) {
+        self.positions.clone_from(&data.positions);
+        self.indicies.clone_from(&data.indicies);
+        self.uvs.clone_from(&data.uvs);
+        self.colors.clone_from(&data.colors);
+        self.normals.clone_from(&data.normals);
+        self.occlusion.clone_from(&data.occlusion);
 
-    pub fn load_buffers(&mut self, gl: &glow::Context) {
-            unsafe {
-                self.position_buffer = gl.create_buffer().expect("Cannot create position buffer");
-                self.color_buffer = gl.create_buffer().expect("Cannot create color buffer");
-                self.uv_buffer = gl.create_buffer().expect("Cannot create uv buffer");
-                self.index_buffer = gl.create_buffer().expect("Cannot create index buffer");
+        self.load_buffers(gl);
+    }
 
-                self.vertex_array = gl.create_vertex_array().expect("Cannot create vertex array");
 
+    pub fn load_buffers(&mut self, gl: &glow::Context) {
+        unsafe {
             // gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
             gl.bind_vertex_array(Some(self.vertex_array));
-            gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.index_buffer));
-            gl.buffer_data_u8_slice(glow::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(&self.indicies.chunks_exact(3).map(|x| {
+
+            let indicies: Vec<u32> = self.indicies.chunks_exact(3).flat_map(|x| {
                 if self.wireframe {
                     [x[0], x[1], x[1], x[2], x[2], x[0]].to_vec()
                 } else {
                     [x[0], x[1], x[2]].to_vec()
                 }
-            } ).flatten().collect::<Vec<u32>>()), glow::STATIC_DRAW);
+            } ).collect();
+
+            self.index_type = if self.positions.len() <= u16::MAX as usize + 1 { glow::UNSIGNED_SHORT } else { glow::UNSIGNED_INT };
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.position_buffer));
-            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(&self.positions.iter().flat_map(|x| {
-                vec![x.x, x.y, x.z, 1.0].into_iter()
-            }).collect::<Vec<f32>>()), glow::STATIC_DRAW);
-            gl.vertex_attrib_pointer_f32(0, 4, glow::FLOAT, false, 0, 0);  // Position (2 floats per vertex)
+            // the element array binding is part of the vertex array state
+            if self.index_type == glow::UNSIGNED_SHORT {
+                self.index_buffer.upload(gl, glow::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(&indicies.iter().map(|&i| i as u16).collect::<Vec<u16>>()));
+            } else {
+                self.index_buffer.upload(gl, glow::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(&indicies));
+            }
+
+            // voxel meshes have integer corners, so positions usually fit in u8 or u16
+            self.position_type = AttribType::smallest_for(self.positions.iter().flat_map(|x| [x.x, x.y, x.z]));
+
+            self.position_buffer.upload(gl, glow::ARRAY_BUFFER, &self.position_type.encode(self.positions.iter().flat_map(|x| [x.x, x.y, x.z])));
+            gl.vertex_attrib_pointer_f32(0, 3, self.position_type.gl_type(), false, 0, 0);  // Position (3 components per vertex, w defaults to 1)
             gl.enable_vertex_attrib_array(0);  // Enable position attribute
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.color_buffer));
-            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(&self.colors.iter().flat_map(|x| {
+            self.color_buffer.upload(gl, glow::ARRAY_BUFFER, &self.colors.iter().flat_map(|x| {
                 if !self.wireframe {
-                    vec![x.x, x.y, x.z, x.w].into_iter()
+                    // premultiplied, translucent colors are blended with `ONE, ONE_MINUS_SRC_ALPHA`
+                    [x.r(), x.g(), x.b(), x.a()]
                 } else {
-                    vec![1.0, 1.0, 1.0, 1.0].into_iter()
+                    [255, 255, 255, 255]
                 }
-                
-            }).collect::<Vec<f32>>()), glow::STATIC_DRAW);
-            gl.vertex_attrib_pointer_f32(1, 4, glow::FLOAT, false, 0, 0);  // Color (4 floats per vertex)
+            }).collect::<Vec<u8>>());
+            gl.vertex_attrib_pointer_f32(1, 4, glow::UNSIGNED_BYTE, true, 0, 0);  // Color (packed RGBA8 per vertex)
             gl.enable_vertex_attrib_array(1);  // Enable color attribute
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.uv_buffer));
-            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(&self.uvs.iter().flat_map(|x|{
-                vec![x.x, x.y].into_iter()
-            }).collect::<Vec<f32>>()), glow::STATIC_DRAW);
-            gl.vertex_attrib_pointer_f32(2, 2, glow::FLOAT, false, 0, 0);
-            gl.enable_vertex_attrib_array(2);  // Enable uv attribute
-
+            if self.uvs.is_empty() {
+                gl.disable_vertex_attrib_array(2);
+            } else {
+                self.uv_buffer.upload(gl, glow::ARRAY_BUFFER, bytemuck::cast_slice(&self.uvs.iter().flat_map(|x| [x.x, x.y]).collect::<Vec<f32>>()));
+                gl.vertex_attrib_pointer_f32(2, 2, glow::FLOAT, false, 0, 0);
+                gl.enable_vertex_attrib_array(2);  // Enable uv attribute
+            }
+
+            // meshes without normals read (0, 0, 0), which the shader leaves unlit
+            if self.normals.is_empty() {
+                gl.disable_vertex_attrib_array(3);
+            } else {
+                self.normal_buffer.upload(gl, glow::ARRAY_BUFFER, bytemuck::cast_slice(&self.normals.iter().flat_map(|x| [x.x, x.y, x.z].map(|c| (c * 127.0) as i8)).collect::<Vec<i8>>()));
+                gl.vertex_attrib_pointer_f32(3, 3, glow::BYTE, true, 0, 0);  // Normal (normalized i8 per component)
+                gl.enable_vertex_attrib_array(3);  // Enable normal attribute
+            }
+
+            if self.occlusion.is_empty() {
+                gl.disable_vertex_attrib_array(4);
+            } else {
+                self.occlusion_buffer.upload(gl, glow::ARRAY_BUFFER, &self.occlusion);
+                gl.vertex_attrib_pointer_f32(4, 1, glow::UNSIGNED_BYTE, false, 0, 0);  // Occlusion level 0-3
+                gl.enable_vertex_attrib_array(4);  // Enable occlusion attribute
+            }
+
+            gl.bind_vertex_array(None);
             self.index_buffer_size = (if self.wireframe {2} else {1})*self.indicies.len() as u32;
         }
     }
 
     pub fn destroy(&self, gl: &glow::Context) {
-        use glow::HasContext as _;
         unsafe {
-            gl.delete_buffer(self.position_buffer);
             gl.delete_vertex_array(self.vertex_array);
-            gl.delete_buffer(self.color_buffer);
-            gl.delete_buffer(self.index_buffer);
-            gl.delete_buffer(self.uv_buffer);
+        }
+        gpu::track_deleted(1);
+
+        for buffer in [&self.position_buffer, &self.color_buffer, &self.index_buffer, &self.uv_buffer, &self.normal_buffer, &self.occlusion_buffer] {
+            buffer.destroy(gl);
         }
     }
 
 }
-
-
-// }
\ No newline at end of file
@@ -0,0 +1,185 @@
+use egui::Color32;
+use nalgebra::{Vector2, Vector3};
+
+
+/// Renderer-agnostic geometry. Produced by the simulation on the CPU and
+/// uploaded to the GPU separately via `Mesh::from_data`.
+///
+/// Positions are in voxel units, the renderer scales them by `VOXEL_WIDTH`.
+/// `uvs`, `normals` and `occlusion` are either empty or have one entry per vertex.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub positions: Vec<Vector3<f32>>,
+    pub indicies: Vec<u32>,
+    pub uvs: Vec<Vector2<f32>>,
+    pub colors: Vec<Color32>,
+    pub normals: Vec<Vector3<f32>>,
+    /// Ambient occlusion level from 0 (open) to 3 (fully enclosed corner).
+    pub occlusion: Vec<u8>,
+}
+
+
+/// One voxel drawn as an instance of `MeshData::cube`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelInstance {
+    pub position: [u16; 3],
+    pub color: Color32,
+}
+
+impl VoxelInstance {
+    /// Size of one instance in the GPU buffer: three u16 coordinates and RGBA8 color.
+    pub const STRIDE: usize = 10;
+
+    /// Translucent colors are drawn opaque, so their premultiplied rgb is brought back to full strength.
+    pub fn encode(instances: &[VoxelInstance]) -> Vec<u8> {
+        instances.iter().flat_map(|instance| {
+            let [x, y, z] = instance.position.map(u16::to_ne_bytes);
+            let color = instance.color.to_opaque();
+            [x[0], x[1], y[0], y[1], z[0], z[1], color.r(), color.g(), color.b(), 255]
+        }).collect()
+    }
+}
+
+
+impl MeshData {
+    pub fn new(positions: Vec<Vector3<f32>>, indicies: Vec<u32>, uvs: Vec<Vector2<f32>>, colors: Vec<Color32>) -> Self {
+        Self {
+            positions,
+            indicies,
+            uvs,
+            colors,
+            normals: Vec::new(),
+            occlusion: Vec::new()
+        }
+    }
+
+    /// Builds a mesh where every vertex is used exactly once, in order.
+    pub fn from_unindexed(positions: Vec<Vector3<f32>>, colors: Vec<Color32>) -> Self {
+        let count = positions.len();
+
+        Self::new(
+            positions,
+            (0..count as u32).collect(),
+            Vec::new(),
+            colors
+        )
+    }
+
+    /// Adds a quad as 4 shared vertices and 2 triangles, `(a, b, c)` and `(b, d, c)`.
+    /// `d` is the corner opposite `a`. The split is flipped to the `a`-`d` diagonal when that
+    /// pair is less occluded, so occlusion interpolates evenly across the quad.
+    pub fn push_quad(&mut self, corners: [Vector3<f32>; 4], normal: Vector3<f32>, color: Color32, occlusion: [u8; 4]) {
+        self.push_shaded_quad(corners, normal, [color; 4], occlusion);
+    }
+
+    /// Same as `push_quad` with a color per corner.
+    pub fn push_shaded_quad(&mut self, [a, b, c, d]: [Vector3<f32>; 4], normal: Vector3<f32>, colors: [Color32; 4], occlusion: [u8; 4]) {
+        let base = self.positions.len() as u32;
+
+        self.positions.extend([a, b, c, d]);
+        self.colors.extend(colors);
+        self.normals.extend([normal; 4]);
+        self.occlusion.extend(occlusion);
+
+        let triangles = if occlusion[0] + occlusion[3] < occlusion[1] + occlusion[2] {
+            [0, 1, 3, 0, 3, 2]
+        } else {
+            [0, 1, 2, 1, 3, 2]
+        };
+        self.indicies.extend(triangles.map(|i| base + i));
+    }
+
+    /// Unit cube from the origin to `(1, 1, 1)` with face normals.
+    pub fn cube() -> Self {
+        let mut mesh = Self::default();
+
+        for d in 0..3 {
+            let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+            for side in [0.0, 1.0] {
+                let corner = |a: f32, b: f32| {
+                    let mut p = Vector3::zeros();
+                    (p[d], p[u], p[v]) = (side, a, b);
+                    p
+                };
+                let mut normal = Vector3::zeros();
+                normal[d] = side * 2.0 - 1.0;
+
+                mesh.push_quad([corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0), corner(1.0, 1.0)], normal, Color32::WHITE, [0; 4]);
+            }
+        }
+
+        mesh
+    }
+
+    /// Splits into `(opaque, translucent)` triangles, going by the alpha of each triangle's
+    /// first vertex. Vertices are copied into whichever half uses them.
+    pub fn split_translucent(&self) -> (MeshData, MeshData) {
+        let mut halves = (MeshData::default(), MeshData::default());
+        let mut remap: Vec<Option<(bool, u32)>> = vec![None; self.positions.len()];
+
+        for triangle in self.indicies.chunks_exact(3) {
+            let translucent = self.colors.get(triangle[0] as usize).is_some_and(|c| c.a() < 255);
+            let half = if translucent { &mut halves.1 } else { &mut halves.0 };
+
+            for &i in triangle {
+                let index = match remap[i as usize] {
+                    Some((t, index)) if t == translucent => index,
+                    _ => {
+                        let index = half.push_vertex_from(self, i as usize);
+                        remap[i as usize] = Some((translucent, index));
+                        index
+                    }
+                };
+                half.indicies.push(index);
+            }
+        }
+
+        halves
+    }
+
+    fn push_vertex_from(&mut self, other: &MeshData, i: usize) -> u32 {
+        self.positions.push(other.positions[i]);
+        self.uvs.extend(other.uvs.get(i));
+        self.colors.extend(other.colors.get(i));
+        self.normals.extend(other.normals.get(i));
+        self.occlusion.extend(other.occlusion.get(i));
+
+        (self.positions.len() - 1) as u32
+    }
+
+    /// Adds the vertices and triangles of `other`.
+    pub fn append(&mut self, other: &MeshData) {
+        let base = self.positions.len() as u32;
+
+        self.positions.extend(&other.positions);
+        self.uvs.extend(&other.uvs);
+        self.colors.extend(&other.colors);
+        self.normals.extend(&other.normals);
+        self.occlusion.extend(&other.occlusion);
+        self.indicies.extend(other.indicies.iter().map(|i| base + i));
+    }
+
+    /// Orders triangles from farthest to nearest to `eye` (in voxel units), so blended
+    /// geometry drawn in index order covers what's behind it.
+    pub fn sort_back_to_front(&mut self, eye: Vector3<f32>) {
+        let mut triangles: Vec<(f32, [u32; 3])> = self.indicies.chunks_exact(3).map(|t| {
+            let center = (self.positions[t[0] as usize] + self.positions[t[1] as usize] + self.positions[t[2] as usize]) / 3.0;
+            ((center - eye).norm_squared(), [t[0], t[1], t[2]])
+        }).collect();
+
+        triangles.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.indicies = triangles.into_iter().flat_map(|(_, t)| t).collect();
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indicies.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indicies.is_empty()
+    }
+}
@@ -0,0 +1,213 @@
+use egui::Color32;
+use web_time::{Duration, Instant};
+
+use crate::chunks::Chunks;
+use crate::mesh_data::MeshData;
+use crate::voxel_manager::{MeshMode, VoxelManager, Voxels};
+
+
+/// Fresh mesh data for the chunks that changed, and how long meshing took.
+pub struct MeshBatch {
+    pub chunks: Vec<(usize, MeshData)>,
+    pub elapsed: Duration,
+}
+
+/// What changed in the grid since the last job.
+enum Update {
+    /// Changed cells with their new contents.
+    Cells(Vec<([usize; 3], Option<Color32>)>),
+    /// The whole grid, when the changes weren't listed. Diffed against the previous one.
+    Grid(Voxels),
+}
+
+struct Job {
+    update: Update,
+    mesh_mode: MeshMode,
+    color_variation: f32,
+    all: bool,
+}
+
+/// Brings the mesher's copy of the grid up to date and meshes the chunks that changed.
+fn run(chunks: &mut Chunks, grid: &mut VoxelManager, job: Job) -> MeshBatch {
+    let start = Instant::now();
+    grid.mesh_mode = job.mesh_mode;
+    grid.color_variation = job.color_variation;
+
+    match job.update {
+        Update::Cells(cells) => {
+            for ([x, y, z], voxel) in cells {
+                grid.set_voxel(x, y, z, voxel);
+            }
+            let changes = grid.take_changes();
+            chunks.apply(&changes, grid);
+        },
+        Update::Grid(voxels) => {
+            let old = grid.set_voxels(voxels);
+            grid.take_changes();
+            chunks.diff(&old, grid);
+        },
+    }
+
+    if job.all {
+        chunks.mark_all_dirty();
+    }
+    let chunks = chunks.remesh(grid);
+
+    MeshBatch {
+        chunks,
+        elapsed: start.elapsed(),
+    }
+}
+
+
+/// Meshes chunks off the UI thread. On native a worker thread owns the `Chunks` and a copy of
+/// the grid, which each job updates with just the cells that changed; the web has no threads,
+/// so there it meshes synchronously behind the same interface. At most one job is in flight.
+/// Requests made meanwhile are merged, and changes are only collected once the next job can start.
+pub struct Mesher {
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: std::sync::mpsc::Sender<Job>,
+    #[cfg(not(target_arch = "wasm32"))]
+    results: std::sync::mpsc::Receiver<MeshBatch>,
+    #[cfg(target_arch = "wasm32")]
+    chunks: Chunks,
+    #[cfg(target_arch = "wasm32")]
+    grid: VoxelManager,
+    #[cfg(target_arch = "wasm32")]
+    ready: Option<MeshBatch>,
+    busy: bool,
+    /// Requested while busy, with whether every chunk should be remeshed.
+    pending: Option<bool>,
+}
+
+
+impl Mesher {
+    /// Starts from `chunks` meshed for the current grid. Changes made before are dropped.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(mut chunks: Chunks, manager: &mut VoxelManager) -> Self {
+        manager.take_changes();
+        let mut grid = manager.snapshot();
+        let (jobs, job_receiver) = std::sync::mpsc::channel::<Job>();
+        let (result_sender, results) = std::sync::mpsc::channel();
+
+        // exits once the mesher is dropped and the job channel closes
+        std::thread::Builder::new()
+            .name("mesher".to_string())
+            .spawn(move || {
+                for job in job_receiver {
+                    if result_sender.send(run(&mut chunks, &mut grid, job)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Cannot spawn mesher thread");
+
+        Self {
+            jobs,
+            results,
+            busy: false,
+            pending: None,
+        }
+    }
+
+    /// Starts from `chunks` meshed for the current grid. Changes made before are dropped.
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(chunks: Chunks, manager: &mut VoxelManager) -> Self {
+        manager.take_changes();
+
+        Self {
+            chunks,
+            grid: manager.snapshot(),
+            ready: None,
+            busy: false,
+            pending: None,
+        }
+    }
+
+    /// Whether a job is being meshed.
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    /// Schedules meshing the chunks that changed, or every chunk with `all`.
+    pub fn request(&mut self, manager: &mut VoxelManager, all: bool) {
+        if self.busy {
+            self.pending = Some(all || self.pending == Some(true));
+        } else {
+            self.submit(manager, all);
+        }
+    }
+
+    /// Takes the result of the finished job, if any, and starts the pending one on the current grid.
+    pub fn poll(&mut self, manager: &mut VoxelManager) -> Option<MeshBatch> {
+        let batch = self.receive()?;
+        self.busy = false;
+
+        if let Some(all) = self.pending.take() {
+            self.submit(manager, all);
+        }
+
+        Some(batch)
+    }
+
+    /// Blocks until everything requested so far is meshed and returns the results merged.
+    pub fn finish(&mut self, manager: &mut VoxelManager) -> Option<MeshBatch> {
+        let mut merged: Option<MeshBatch> = None;
+
+        while self.busy {
+            #[cfg(not(target_arch = "wasm32"))]
+            let batch = self.results.recv().expect("Mesher thread has stopped");
+            #[cfg(target_arch = "wasm32")]
+            let batch = self.ready.take().expect("Mesher has no result");
+
+            self.busy = false;
+            if let Some(all) = self.pending.take() {
+                self.submit(manager, all);
+            }
+
+            merged = Some(match merged {
+                Some(mut merged) => {
+                    merged.chunks.extend(batch.chunks);
+                    merged.elapsed += batch.elapsed;
+                    merged
+                },
+                None => batch,
+            });
+        }
+
+        merged
+    }
+
+    fn submit(&mut self, manager: &mut VoxelManager, all: bool) {
+        let changes = manager.take_changes();
+        let update = match changes.cells() {
+            Some(cells) => Update::Cells(cells.iter().map(|&[x, y, z]| ([x, y, z], manager.get_voxel(x, y, z))).collect()),
+            None => Update::Grid(manager.voxels().clone()),
+        };
+        let job = Job {
+            update,
+            mesh_mode: manager.mesh_mode,
+            color_variation: manager.color_variation,
+            all,
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.jobs.send(job).expect("Mesher thread has stopped");
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.ready = Some(run(&mut self.chunks, &mut self.grid, job));
+        }
+
+        self.busy = true;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn receive(&mut self) -> Option<MeshBatch> {
+        self.results.try_recv().ok()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn receive(&mut self) -> Option<MeshBatch> {
+        self.ready.take()
+    }
+}
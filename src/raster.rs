@@ -0,0 +1,227 @@
+use egui::Color32;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::camera::Camera;
+use crate::mesh_data::MeshData;
+use crate::screenshot::{encode_png, unpremultiply};
+use crate::lighting::Lighting;
+use crate::voxel_manager::VOXEL_WIDTH;
+
+
+/// RGBA pixels, top row first. Colors are premultiplied, like the GPU framebuffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    pub fn encode_png(&self) -> Vec<u8> {
+        let mut pixels = self.pixels.clone();
+        unpremultiply(&mut pixels);
+        encode_png(self.width as u32, self.height as u32, &pixels)
+    }
+}
+
+
+/// Vertex after the vertex stage, in clip space.
+#[derive(Debug, Clone, Copy)]
+struct ClipVertex {
+    position: Vector4<f32>,
+    color: Vector4<f32>,
+    normal: Vector3<f32>,
+    occlusion: f32,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position.lerp(&other.position, t),
+            color: self.color.lerp(&other.color, t),
+            normal: self.normal.lerp(&other.normal, t),
+            occlusion: self.occlusion + (other.occlusion - self.occlusion) * t,
+        }
+    }
+}
+
+
+/// Renders mesh data on the CPU the way the GPU renderer draws it, minus shadows: depth
+/// tested, lit by the directional light with ambient occlusion, and translucent triangles
+/// blended back to front over the opaque ones. For machines without a GPU and image tests.
+#[derive(Debug, Clone)]
+pub struct Rasterizer {
+    pub width: usize,
+    pub height: usize,
+    pub background: Color32,
+    pub lighting: Lighting,
+}
+
+impl Rasterizer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            background: Color32::TRANSPARENT,
+            lighting: Lighting::default(),
+        }
+    }
+
+    pub fn render(&self, meshes: &[MeshData], camera: &Camera) -> Image {
+        let mut camera = camera.clone();
+        camera.aspect_ratio = self.width as f32 / self.height as f32;
+        let view_proj = camera.get_proj_view_mat();
+
+        let mut image = Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.background.to_array().repeat(self.width * self.height),
+        };
+        let mut depth = vec![f32::INFINITY; self.width * self.height];
+
+        let mut translucent = MeshData::default();
+        for mesh in meshes {
+            let (opaque, blended) = mesh.split_translucent();
+            self.draw(&mut image, &mut depth, &opaque, &view_proj, false);
+            translucent.append(&blended);
+        }
+
+        let eye = Vector3::new(camera.pos.x, -camera.pos.y, camera.pos.z) / VOXEL_WIDTH;
+        translucent.sort_back_to_front(eye);
+        self.draw(&mut image, &mut depth, &translucent, &view_proj, true);
+
+        image
+    }
+
+    fn draw(&self, image: &mut Image, depth: &mut [f32], mesh: &MeshData, view_proj: &Matrix4<f32>, blend: bool) {
+        let vertices: Vec<ClipVertex> = (0..mesh.vertex_count()).map(|i| {
+            // same as the vertex shader: scale to world units and flip y
+            let p = mesh.positions[i] * VOXEL_WIDTH;
+            let color = mesh.colors.get(i).copied().unwrap_or(Color32::WHITE);
+
+            ClipVertex {
+                position: view_proj * Vector4::new(p.x, -p.y, p.z, 1.0),
+                color: Vector4::from(color.to_array().map(|c| c as f32 / 255.0)),
+                normal: mesh.normals.get(i).copied().unwrap_or_else(Vector3::zeros),
+                occlusion: mesh.occlusion.get(i).copied().unwrap_or(0) as f32,
+            }
+        }).collect();
+
+        for triangle in mesh.indicies.chunks_exact(3) {
+            let polygon = clip_near([0, 1, 2].map(|k| vertices[triangle[k] as usize]));
+            for k in 1..polygon.len().saturating_sub(1) {
+                self.fill(image, depth, [polygon[0], polygon[k], polygon[k + 1]], blend);
+            }
+        }
+    }
+
+    /// Same as the fragment shader without the shadow term.
+    fn shade(&self, v: &ClipVertex) -> Vector4<f32> {
+        let mut light = 1.0;
+        if v.normal.norm_squared() > 0.0 {
+            let ambient = self.lighting.ambient;
+            light = ambient + (1.0 - ambient) * v.normal.normalize().dot(&self.lighting.direction()).max(0.0);
+        }
+        light *= 1.0 - self.lighting.ambient_occlusion * v.occlusion / 3.0;
+
+        Vector4::new(v.color.x * light, v.color.y * light, v.color.z * light, v.color.w)
+    }
+
+    fn fill(&self, image: &mut Image, depth: &mut [f32], triangle: [ClipVertex; 3], blend: bool) {
+        let (width, height) = (self.width as f32, self.height as f32);
+
+        // window coordinates, with y down and depth in 0..1
+        let screen = triangle.map(|v| {
+            let ndc = v.position.xyz() / v.position.w;
+            Vector3::new((ndc.x + 1.0) * 0.5 * width, (1.0 - ndc.y) * 0.5 * height, ndc.z * 0.5 + 0.5)
+        });
+        let inv_w = triangle.map(|v| 1.0 / v.position.w);
+
+        let edge = |a: Vector3<f32>, b: Vector3<f32>, x: f32, y: f32| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+        let area = edge(screen[0], screen[1], screen[2].x, screen[2].y);
+        if area == 0.0 {
+            return;
+        }
+
+        // top-left rule, so pixels on shared edges are drawn once
+        let owns = |a: Vector3<f32>, b: Vector3<f32>| {
+            let (dx, dy) = ((b.x - a.x) * area.signum(), (b.y - a.y) * area.signum());
+            dy < 0.0 || (dy == 0.0 && dx > 0.0)
+        };
+        let edges = [(1, 2), (2, 0), (0, 1)];
+        let owned = edges.map(|(a, b)| owns(screen[a], screen[b]));
+
+        let min_x = screen.iter().map(|p| p.x).fold(f32::MAX, f32::min).floor().max(0.0) as usize;
+        let max_x = screen.iter().map(|p| p.x).fold(f32::MIN, f32::max).ceil().min(width) as usize;
+        let min_y = screen.iter().map(|p| p.y).fold(f32::MAX, f32::min).floor().max(0.0) as usize;
+        let max_y = screen.iter().map(|p| p.y).fold(f32::MIN, f32::max).ceil().min(height) as usize;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                let weights = edges.map(|(a, b)| edge(screen[a], screen[b], px, py) / area);
+                if (0..3).any(|k| weights[k] < 0.0 || (weights[k] == 0.0 && !owned[k])) {
+                    continue;
+                }
+
+                let z = (0..3).map(|k| weights[k] * screen[k].z).sum::<f32>();
+                let i = y * self.width + x;
+                if !(0.0..=1.0).contains(&z) || z >= depth[i] {
+                    continue;
+                }
+
+                // perspective correct interpolation
+                let perspective = [0, 1, 2].map(|k| weights[k] * inv_w[k]);
+                let total: f32 = perspective.iter().sum();
+                let mut v = triangle[0];
+                v.color = (0..3).map(|k| triangle[k].color * perspective[k]).sum::<Vector4<f32>>() / total;
+                v.normal = (0..3).map(|k| triangle[k].normal * perspective[k]).sum::<Vector3<f32>>() / total;
+                v.occlusion = (0..3).map(|k| triangle[k].occlusion * perspective[k]).sum::<f32>() / total;
+
+                let color = self.shade(&v);
+                let pixel = &mut image.pixels[i * 4..i * 4 + 4];
+                for c in 0..4 {
+                    let value = if blend {
+                        // premultiplied `ONE, ONE_MINUS_SRC_ALPHA`
+                        color[c] + pixel[c] as f32 / 255.0 * (1.0 - color.w)
+                    } else {
+                        color[c]
+                    };
+                    pixel[c] = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+
+                if !blend {
+                    depth[i] = z;
+                }
+            }
+        }
+    }
+}
+
+
+/// Clips a triangle against the near plane, `z >= -w`, into a convex polygon of up to four
+/// vertices. Everything else is clipped per pixel.
+fn clip_near(triangle: [ClipVertex; 3]) -> Vec<ClipVertex> {
+    let distance = |v: &ClipVertex| v.position.z + v.position.w;
+    let mut polygon = Vec::with_capacity(4);
+
+    for k in 0..3 {
+        let (a, b) = (&triangle[k], &triangle[(k + 1) % 3]);
+        let (da, db) = (distance(a), distance(b));
+
+        if da >= 0.0 {
+            polygon.push(*a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            polygon.push(a.lerp(b, da / (da - db)));
+        }
+    }
+
+    polygon
+}
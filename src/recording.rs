@@ -0,0 +1,107 @@
+use crate::gif::GifEncoder;
+use crate::screenshot::encode_png;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingFormat {
+    #[default]
+    Gif,
+    PngSequence,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Simulation steps between captured frames.
+    pub every: usize,
+    /// Playback rate of the GIF.
+    pub fps: u32,
+    pub format: RecordingFormat,
+    /// Finish meshing before every capture, so frames never lag behind the simulation
+    /// however slow meshing is.
+    pub lock_tick: bool,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            every: 2,
+            fps: 25,
+            format: RecordingFormat::default(),
+            lock_tick: true,
+        }
+    }
+}
+
+
+/// Frames encoded as they come in, so a long recording isn't kept as raw pixels.
+#[derive(Debug)]
+enum Frames {
+    Gif(GifEncoder),
+    Png(Vec<Vec<u8>>),
+}
+
+/// Finished recording.
+#[derive(Debug)]
+pub enum RecordingOutput {
+    Gif(Vec<u8>),
+    /// One PNG file per frame.
+    PngSequence(Vec<Vec<u8>>),
+}
+
+
+/// Collects a frame every `settings.every` simulation steps.
+#[derive(Debug)]
+pub struct Recorder {
+    pub settings: RecordingSettings,
+    frames: Frames,
+    steps: usize,
+    pub frame_count: usize,
+}
+
+impl Recorder {
+    pub fn new(settings: RecordingSettings) -> Self {
+        let frames = match settings.format {
+            RecordingFormat::Gif => {
+                // GIF delays are in hundredths of a second
+                let delay = (100 / settings.fps.max(1)).max(1) as u16;
+                Frames::Gif(GifEncoder::new(settings.width as u16, settings.height as u16, delay))
+            },
+            RecordingFormat::PngSequence => Frames::Png(Vec::new()),
+        };
+
+        Self {
+            settings,
+            frames,
+            steps: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// Counts a simulation step and returns whether it should be captured. The first step is.
+    pub fn step(&mut self) -> bool {
+        let capture = self.steps.is_multiple_of(self.settings.every.max(1));
+        self.steps += 1;
+        capture
+    }
+
+    /// Adds opaque RGBA pixels of `settings.width` by `settings.height`, top row first.
+    pub fn add_frame(&mut self, pixels: &[u8]) {
+        match &mut self.frames {
+            Frames::Gif(encoder) => encoder.add_frame(pixels),
+            Frames::Png(files) => files.push(encode_png(self.settings.width, self.settings.height, pixels)),
+        }
+        self.frame_count += 1;
+    }
+
+    pub fn finish(self) -> RecordingOutput {
+        match self.frames {
+            Frames::Gif(encoder) => RecordingOutput::Gif(encoder.finish()),
+            Frames::Png(files) => RecordingOutput::PngSequence(files),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use eframe::glow;
+use egui::Rgba;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::{instancing::InstancedCubes, mesh::Mesh};
+
+
+/// Value of a shader uniform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Uniform {
+    F32(f32),
+    I32(i32),
+    Vec3(Vector3<f32>),
+    Vec4(Vector4<f32>),
+    Mat4(Matrix4<f32>),
+}
+
+impl Uniform {
+    /// Zero of the same type, which is what uniforms hold after the program is linked.
+    pub fn zero(&self) -> Self {
+        match self {
+            Uniform::F32(_) => Uniform::F32(0.0),
+            Uniform::I32(_) => Uniform::I32(0),
+            Uniform::Vec3(_) => Uniform::Vec3(Vector3::zeros()),
+            Uniform::Vec4(_) => Uniform::Vec4(Vector4::zeros()),
+            Uniform::Mat4(_) => Uniform::Mat4(Matrix4::zeros()),
+        }
+    }
+}
+
+impl From<f32> for Uniform {
+    fn from(value: f32) -> Self {
+        Uniform::F32(value)
+    }
+}
+
+impl From<i32> for Uniform {
+    fn from(value: i32) -> Self {
+        Uniform::I32(value)
+    }
+}
+
+/// GLSL `bool` uniforms are set as integers.
+impl From<bool> for Uniform {
+    fn from(value: bool) -> Self {
+        Uniform::I32(value as i32)
+    }
+}
+
+impl From<Vector3<f32>> for Uniform {
+    fn from(value: Vector3<f32>) -> Self {
+        Uniform::Vec3(value)
+    }
+}
+
+impl From<Vector4<f32>> for Uniform {
+    fn from(value: Vector4<f32>) -> Self {
+        Uniform::Vec4(value)
+    }
+}
+
+impl From<Matrix4<f32>> for Uniform {
+    fn from(value: Matrix4<f32>) -> Self {
+        Uniform::Mat4(value)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Blend {
+    /// Overwrites the target.
+    #[default]
+    Opaque,
+    /// Premultiplied alpha, `ONE, ONE_MINUS_SRC_ALPHA`.
+    Alpha,
+    /// Adds to the target, for glows and highlights.
+    Additive,
+}
+
+
+/// What a draw item renders.
+#[derive(Debug, Clone, Copy)]
+pub enum Geometry<'a> {
+    Mesh(&'a Mesh),
+    Instances(&'a InstancedCubes),
+}
+
+impl Geometry<'_> {
+    /// Issues the draw call. Instances are always drawn as triangles.
+    pub fn draw(&self, gl: &glow::Context, primitive: u32) {
+        use glow::HasContext as _;
+
+        match self {
+            Geometry::Mesh(mesh) => unsafe {
+                gl.bind_vertex_array(Some(mesh.vertex_array));
+                gl.draw_elements(primitive, mesh.index_buffer_size as i32, mesh.index_type, 0);
+            },
+            Geometry::Instances(instances) => instances.draw(gl),
+        }
+    }
+}
+
+
+/// One draw call with its render state. Uniforms not set on the item keep the values the
+/// renderer set for the frame. Item uniforms the frame doesn't set are zeroed after drawing.
+#[derive(Debug, Clone)]
+pub struct DrawItem<'a> {
+    pub geometry: Geometry<'a>,
+    /// `glow::TRIANGLES`, `glow::LINES`, ...
+    pub primitive: u32,
+    pub blend: Blend,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// Drawn into the shadow map as well.
+    pub casts_shadow: bool,
+    /// Applied to positions in voxel units, before the renderer scales and flips them.
+    pub model: Matrix4<f32>,
+    /// Multiplies the vertex colors.
+    pub tint: Rgba,
+    pub uniforms: Vec<(&'static str, Uniform)>,
+}
+
+impl<'a> DrawItem<'a> {
+    /// Draws the mesh as lines if it's a wireframe, triangles otherwise.
+    pub fn mesh(mesh: &'a Mesh) -> Self {
+        Self::new(Geometry::Mesh(mesh), if mesh.wireframe { glow::LINES } else { glow::TRIANGLES })
+    }
+
+    pub fn instances(instances: &'a InstancedCubes) -> Self {
+        Self::new(Geometry::Instances(instances), glow::TRIANGLES)
+    }
+
+    fn new(geometry: Geometry<'a>, primitive: u32) -> Self {
+        Self {
+            geometry,
+            primitive,
+            blend: Blend::Opaque,
+            depth_test: true,
+            depth_write: true,
+            casts_shadow: false,
+            model: Matrix4::identity(),
+            tint: Rgba::WHITE,
+            uniforms: Vec::new(),
+        }
+    }
+
+    pub fn primitive(mut self, primitive: u32) -> Self {
+        self.primitive = primitive;
+        self
+    }
+
+    /// Blended items don't write depth, so they don't hide each other.
+    pub fn blend(mut self, blend: Blend) -> Self {
+        self.blend = blend;
+        self.depth_write = blend == Blend::Opaque;
+        self
+    }
+
+    pub fn depth(mut self, test: bool, write: bool) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self
+    }
+
+    pub fn casts_shadow(mut self) -> Self {
+        self.casts_shadow = true;
+        self
+    }
+
+    pub fn model(mut self, model: Matrix4<f32>) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn tint(mut self, tint: impl Into<Rgba>) -> Self {
+        self.tint = tint.into();
+        self
+    }
+
+    pub fn uniform(mut self, name: &'static str, value: impl Into<Uniform>) -> Self {
+        self.uniforms.push((name, value.into()));
+        self
+    }
+
+    /// Whether there's anything to draw.
+    pub fn is_empty(&self) -> bool {
+        match self.geometry {
+            Geometry::Mesh(mesh) => mesh.index_buffer_size == 0,
+            Geometry::Instances(instances) => instances.count == 0,
+        }
+    }
+
+    pub fn sorting(&self) -> Sorting {
+        Sorting {
+            blend: self.blend,
+            casts_shadow: self.casts_shadow,
+            empty: self.is_empty(),
+        }
+    }
+}
+
+
+/// What the queue orders draw items by, apart from their GL objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sorting {
+    pub blend: Blend,
+    pub casts_shadow: bool,
+    pub empty: bool,
+}
+
+/// Indices of `items` in drawing order: opaque ones first, then blended ones, each in
+/// submission order. Empty items are skipped.
+pub fn draw_order(items: &[Sorting]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..items.len()).filter(|&i| !items[i].empty).collect();
+    // stable, so submission order is kept within each group
+    order.sort_by_key(|&i| items[i].blend != Blend::Opaque);
+    order
+}
+
+/// Indices of the items drawn into the shadow map, in submission order.
+pub fn shadow_order(items: &[Sorting]) -> Vec<usize> {
+    (0..items.len()).filter(|&i| items[i].casts_shadow && !items[i].empty).collect()
+}
+
+
+/// Draw items for one frame. Opaque items are drawn first in submission order, then
+/// blended ones, also in submission order.
+#[derive(Debug, Clone)]
+pub struct RenderQueue<'a> {
+    pub items: Vec<DrawItem<'a>>,
+    /// Box in voxel units the shadow map covers.
+    pub bounds: (Vector3<f32>, Vector3<f32>),
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new(bounds: (Vector3<f32>, Vector3<f32>)) -> Self {
+        Self {
+            items: Vec::new(),
+            bounds,
+        }
+    }
+
+    pub fn push(&mut self, item: DrawItem<'a>) {
+        self.items.push(item);
+    }
+
+    fn sorting(&self) -> Vec<Sorting> {
+        self.items.iter().map(DrawItem::sorting).collect()
+    }
+
+    /// Items in drawing order, see `draw_order`.
+    pub fn ordered(&self) -> impl Iterator<Item = &DrawItem<'a>> {
+        draw_order(&self.sorting()).into_iter().map(|i| &self.items[i])
+    }
+
+    pub fn shadow_casters(&self) -> impl Iterator<Item = &DrawItem<'a>> {
+        shadow_order(&self.sorting()).into_iter().map(|i| &self.items[i])
+    }
+}
@@ -0,0 +1,347 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use egui::Color32;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use crate::voxel_manager::{Changes, VoxelManager, Voxels};
+
+
+/// Cells a rule looks at to compute the next state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// The rule sweeps the grid in place and moves voxels itself, like falling sand.
+    Sweep,
+    /// The 6 face-adjacent cells.
+    VonNeumann,
+    /// All 26 surrounding cells.
+    Moore,
+    /// Non-overlapping 2x2x2 blocks whose partition shifts by one cell every other tick.
+    Margolus,
+}
+
+impl Neighborhood {
+    pub fn offsets(&self) -> Vec<(i32, i32, i32)> {
+        match self {
+            Neighborhood::VonNeumann => vec![(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)],
+            Neighborhood::Moore => (-1..=1).flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+                .filter(|&offset| offset != (0, 0, 0))
+                .collect(),
+            Neighborhood::Sweep | Neighborhood::Margolus => Vec::new(),
+        }
+    }
+}
+
+
+/// A 3D cellular automaton run by `VoxelManager::update`.
+///
+/// `VonNeumann` and `Moore` rules implement `transition`, which is applied to every cell
+/// simultaneously. `Margolus` rules implement `transition_block`. `Sweep` rules override `step`.
+pub trait VoxelRule: Send + Sync {
+    fn name(&self) -> String;
+
+    fn neighborhood(&self) -> Neighborhood;
+
+    /// Next state of a cell given its current state and its neighbors, in `Neighborhood::offsets` order.
+    /// Neighbors outside the grid are empty.
+    fn transition(&self, cell: Option<Color32>, _neighbors: &[Option<Color32>]) -> Option<Color32> {
+        cell
+    }
+
+    /// Next state of a 2x2x2 block, indexed `x + 2 * y + 4 * z`.
+    fn transition_block(&self, block: [Option<Color32>; 8]) -> [Option<Color32>; 8] {
+        block
+    }
+
+    /// Advances the grid by one tick, records every cell it changes in `changes`, and returns
+    /// whether anything changed.
+    fn step(&self, voxels: &mut Voxels, tick: usize, changes: &mut Changes) -> bool {
+        match self.neighborhood() {
+            Neighborhood::Sweep => false,
+            Neighborhood::VonNeumann | Neighborhood::Moore => step_cells(self, voxels, changes),
+            Neighborhood::Margolus => step_blocks(self, voxels, tick, changes),
+        }
+    }
+}
+
+
+fn dimensions(voxels: &Voxels) -> (usize, usize, usize) {
+    let width = voxels.len();
+    let height = voxels.first().map_or(0, |column| column.len());
+    let length = voxels.first().and_then(|column| column.first()).map_or(0, |row| row.len());
+    (width, height, length)
+}
+
+fn step_cells<R: VoxelRule + ?Sized>(rule: &R, voxels: &mut Voxels, changes: &mut Changes) -> bool {
+    let (width, height, length) = dimensions(voxels);
+    let offsets = rule.neighborhood().offsets();
+    let previous = voxels.clone();
+    let mut neighbors = Vec::with_capacity(offsets.len());
+    let mut changed = false;
+
+    for x in 0..width {
+        for y in 0..height {
+            for z in 0..length {
+                neighbors.clear();
+                neighbors.extend(offsets.iter().map(|offset| {
+                    let (nx, ny, nz) = (x as i32 + offset.0, y as i32 + offset.1, z as i32 + offset.2);
+                    if nx < 0 || nx >= width as i32 || ny < 0 || ny >= height as i32 || nz < 0 || nz >= length as i32 {
+                        None
+                    } else {
+                        previous[nx as usize][ny as usize][nz as usize]
+                    }
+                }));
+
+                let next = rule.transition(previous[x][y][z], &neighbors);
+                if next != previous[x][y][z] {
+                    voxels[x][y][z] = next;
+                    changes.push(x, y, z);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+fn step_blocks<R: VoxelRule + ?Sized>(rule: &R, voxels: &mut Voxels, tick: usize, changes: &mut Changes) -> bool {
+    let (width, height, length) = dimensions(voxels);
+    let shift = tick % 2;
+    let mut changed = false;
+
+    for bx in (shift..width.saturating_sub(1)).step_by(2) {
+        for by in (shift..height.saturating_sub(1)).step_by(2) {
+            for bz in (shift..length.saturating_sub(1)).step_by(2) {
+                let cell = |i: usize| (bx + (i & 1), by + ((i >> 1) & 1), bz + ((i >> 2) & 1));
+
+                let block: [Option<Color32>; 8] = std::array::from_fn(|i| {
+                    let (x, y, z) = cell(i);
+                    voxels[x][y][z]
+                });
+
+                let next = rule.transition_block(block);
+                if next != block {
+                    for (i, voxel) in next.into_iter().enumerate() {
+                        if voxel != block[i] {
+                            let (x, y, z) = cell(i);
+                            voxels[x][y][z] = voxel;
+                            changes.push(x, y, z);
+                        }
+                    }
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+
+/// The original falling sand rule: drop straight down, otherwise slide to a random free cell diagonally below.
+pub struct SandRule;
+
+impl VoxelRule for SandRule {
+    fn name(&self) -> String {
+        "Sand".to_string()
+    }
+
+    fn neighborhood(&self) -> Neighborhood {
+        Neighborhood::Sweep
+    }
+
+    fn step(&self, voxels: &mut Voxels, _tick: usize, changes: &mut Changes) -> bool {
+        let (width, height, length) = dimensions(voxels);
+        let mut changed = false;
+
+        for y in 0..height {
+            for x in 0..width {
+                for z in 0..length {
+                    if voxels[x][y][z].is_none() {
+                        continue;
+                    }
+
+                    if y != 0 && voxels[x][y-1][z].is_none() {
+                        let color  = voxels[x][y][z].unwrap();
+                        voxels[x][y][z] = None;
+                        voxels[x][y-1][z] = Some(color);
+                        changes.push(x, y, z);
+                        changes.push(x, y - 1, z);
+                        changed = true;
+                    } else {
+                        let mut offsets: Vec<(i32, i32, i32)> = vec![
+                            (1, -1, 0),
+                            (-1, -1, 0),
+                            (0, -1, 1),
+                            (0, -1, -1),
+                            (1, -1, 1),
+                            (1, -1, -1),
+                            (-1, -1, 1),
+                            (-1, -1, -1),
+                        ];
+
+                        offsets.shuffle(&mut thread_rng());
+
+                        for offset in offsets.iter() {
+                            let target: (i32, i32, i32) = (x as i32 + offset.0, y as i32 + offset.1, z as i32 + offset.2);
+                            if target.0 < 0 || target.0 >= width as i32 ||  target.1 < 0 || target.1 >= height as i32 || target.2 < 0 || target.2 >= length as i32 {
+                                continue;
+                            }
+
+                            if voxels[target.0 as usize][target.1 as usize][target.2 as usize].is_none() {
+                                let color = voxels[x][y][z].unwrap();
+                                voxels[x][y][z] = None;
+                                voxels[target.0 as usize][target.1 as usize][target.2 as usize] = Some(color);
+                                changes.push(x, y, z);
+                                changes.push(target.0 as usize, target.1 as usize, target.2 as usize);
+                                changed = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+
+/// 3D Game of Life in Bays' notation: a live cell survives with `survive` live neighbors,
+/// an empty cell comes alive with `birth` live neighbors. "4555" is survive 4-5, birth 5-5.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifeRule {
+    pub survive: RangeInclusive<usize>,
+    pub birth: RangeInclusive<usize>,
+    pub neighborhood: Neighborhood,
+}
+
+impl LifeRule {
+    pub fn new(survive: RangeInclusive<usize>, birth: RangeInclusive<usize>, neighborhood: Neighborhood) -> Self {
+        Self {
+            survive,
+            birth,
+            neighborhood
+        }
+    }
+}
+
+impl FromStr for LifeRule {
+    type Err = String;
+
+    /// Parses `ElEuFlFu` with single digits ("4555") or comma separated bounds ("4,5,5,5").
+    /// A trailing `V` selects the von Neumann neighborhood instead of Moore.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (s, neighborhood) = match s.strip_suffix(['V', 'v']) {
+            Some(s) => (s, Neighborhood::VonNeumann),
+            None => (s, Neighborhood::Moore),
+        };
+
+        let bounds: Vec<usize> = if s.contains(',') {
+            s.split(',').map(|n| n.trim().parse::<usize>().map_err(|e| format!("invalid bound {n:?}: {e}"))).collect::<Result<_, _>>()?
+        } else {
+            s.chars().map(|c| c.to_digit(10).map(|d| d as usize).ok_or(format!("invalid digit {c:?}"))).collect::<Result<_, _>>()?
+        };
+
+        if bounds.len() != 4 {
+            return Err(format!("expected 4 bounds, found {}", bounds.len()));
+        }
+
+        Ok(Self::new(bounds[0]..=bounds[1], bounds[2]..=bounds[3], neighborhood))
+    }
+}
+
+impl VoxelRule for LifeRule {
+    fn name(&self) -> String {
+        let suffix = if self.neighborhood == Neighborhood::VonNeumann { "V" } else { "" };
+        format!("Life {},{},{},{}{suffix}", self.survive.start(), self.survive.end(), self.birth.start(), self.birth.end())
+    }
+
+    fn neighborhood(&self) -> Neighborhood {
+        self.neighborhood
+    }
+
+    fn transition(&self, cell: Option<Color32>, neighbors: &[Option<Color32>]) -> Option<Color32> {
+        let alive: Vec<Color32> = neighbors.iter().flatten().copied().collect();
+
+        match cell {
+            Some(_) if self.survive.contains(&alive.len()) => cell,
+            Some(_) => None,
+            None if alive.is_empty() && self.birth.contains(&0) => {
+                // born from nothing, like "0,0,0,0", so there are no parents to take after
+                VoxelManager::colors().choose(&mut thread_rng()).copied()
+            },
+            None if self.birth.contains(&alive.len()) => {
+                // newborn cells take the average color of their parents
+                let n = alive.len() as u32;
+                let sum = alive.iter().fold([0u32; 3], |acc, c| [acc[0] + c.r() as u32, acc[1] + c.g() as u32, acc[2] + c.b() as u32]);
+                Some(Color32::from_rgb((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8))
+            },
+            None => None,
+        }
+    }
+}
+
+
+/// Falling sand on a Margolus neighborhood: within each block, grains in the upper layer fall
+/// straight down if they can, otherwise topple into a random free cell of the lower layer.
+pub struct MargolusSandRule;
+
+impl VoxelRule for MargolusSandRule {
+    fn name(&self) -> String {
+        "Margolus sand".to_string()
+    }
+
+    fn neighborhood(&self) -> Neighborhood {
+        Neighborhood::Margolus
+    }
+
+    fn transition_block(&self, mut block: [Option<Color32>; 8]) -> [Option<Color32>; 8] {
+        // upper layer has y = 1, the cell below index i is i - 2
+        let mut upper = [2, 3, 6, 7];
+        upper.shuffle(&mut thread_rng());
+
+        for i in upper {
+            if block[i].is_none() {
+                continue;
+            }
+
+            let below = i - 2;
+            let target = if block[below].is_none() {
+                Some(below)
+            } else {
+                let mut lower = [0, 1, 4, 5];
+                lower.shuffle(&mut thread_rng());
+                lower.into_iter().find(|&j| block[j].is_none())
+            };
+
+            if let Some(j) = target {
+                block[j] = block[i].take();
+            }
+        }
+
+        block
+    }
+}
+
+
+impl std::fmt::Debug for dyn VoxelRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+
+/// Rules selectable from the UI.
+pub fn builtin_rules() -> Vec<Arc<dyn VoxelRule>> {
+    vec![
+        Arc::new(SandRule),
+        Arc::new(MargolusSandRule),
+        Arc::new("4555".parse::<LifeRule>().unwrap()),
+        Arc::new("5766".parse::<LifeRule>().unwrap()),
+    ]
+}
@@ -0,0 +1,134 @@
+#[cfg(feature = "gl")]
+use eframe::glow::{self, HasContext as _};
+
+#[cfg(feature = "gl")]
+use crate::gpu;
+
+
+/// Offscreen color and depth buffers to render a frame at any resolution.
+#[cfg(feature = "gl")]
+#[derive(Debug)]
+pub struct RenderTarget {
+    pub framebuffer: glow::Framebuffer,
+    color: glow::Renderbuffer,
+    depth: glow::Renderbuffer,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[cfg(feature = "gl")]
+impl RenderTarget {
+    /// Clamps the size to what the driver supports.
+    pub fn new(gl: &glow::Context, width: i32, height: i32) -> Self {
+        unsafe {
+            let max = gl.get_parameter_i32(glow::MAX_RENDERBUFFER_SIZE);
+            let (width, height) = (width.clamp(1, max), height.clamp(1, max));
+
+            let framebuffer = gl.create_framebuffer().expect("Cannot create framebuffer");
+            let color = gl.create_renderbuffer().expect("Cannot create renderbuffer");
+            let depth = gl.create_renderbuffer().expect("Cannot create renderbuffer");
+            gpu::track_created(3);
+
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(color));
+            gl.renderbuffer_storage(glow::RENDERBUFFER, glow::RGBA8, width, height);
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(depth));
+            gl.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH_COMPONENT24, width, height);
+            gl.bind_renderbuffer(glow::RENDERBUFFER, None);
+
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::RENDERBUFFER, Some(color));
+            gl.framebuffer_renderbuffer(glow::FRAMEBUFFER, glow::DEPTH_ATTACHMENT, glow::RENDERBUFFER, Some(depth));
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+
+            Self {
+                framebuffer,
+                color,
+                depth,
+                width,
+                height,
+            }
+        }
+    }
+
+    /// Draws into the target with `draw` and returns the RGBA pixels, top row first.
+    /// The previous framebuffer, viewport and scissor state are restored afterwards.
+    pub fn capture(&self, gl: &glow::Context, clear_color: [f32; 4], draw: impl FnOnce(&glow::Context)) -> Vec<u8> {
+        unsafe {
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            let mut viewport = [0; 4];
+            gl.get_parameter_i32_slice(glow::VIEWPORT, &mut viewport);
+            let scissor = gl.is_enabled(glow::SCISSOR_TEST);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.viewport(0, 0, self.width, self.height);
+            gl.disable(glow::SCISSOR_TEST);
+            gl.depth_mask(true);
+            let [r, g, b, a] = clear_color;
+            gl.clear_color(r, g, b, a);
+            gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
+
+            draw(gl);
+
+            let mut pixels = vec![0; (self.width * self.height * 4) as usize];
+            gl.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+            gl.read_pixels(0, 0, self.width, self.height, glow::RGBA, glow::UNSIGNED_BYTE, glow::PixelPackData::Slice(Some(&mut pixels)));
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+            gl.viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            if scissor {
+                gl.enable(glow::SCISSOR_TEST);
+            }
+
+            flip_rows(&mut pixels, self.width as usize);
+            pixels
+        }
+    }
+
+    pub fn destroy(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_renderbuffer(self.color);
+            gl.delete_renderbuffer(self.depth);
+        }
+        gpu::track_deleted(3);
+    }
+}
+
+
+/// Reverses the row order of tightly packed RGBA pixels, since GL reads bottom row first.
+pub fn flip_rows(pixels: &mut [u8], width: usize) {
+    let stride = width * 4;
+    let rows = pixels.len() / stride;
+
+    for row in 0..rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((rows - 1 - row) * stride);
+        top[row * stride..(row + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+    }
+}
+
+/// Converts the premultiplied colors the renderer blends with to the straight alpha PNG expects.
+pub fn unpremultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let a = pixel[3];
+        if a != 0 && a != 255 {
+            for c in pixel[..3].iter_mut() {
+                *c = ((*c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Encodes straight alpha RGBA pixels, top row first.
+pub fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().expect("Cannot write PNG header");
+    writer.write_image_data(pixels).expect("Cannot write PNG data");
+    writer.finish().expect("Cannot finish PNG");
+
+    bytes
+}
@@ -0,0 +1,209 @@
+use std::sync::{Arc, Mutex};
+
+use egui::Color32;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::voxel_manager::Voxels;
+
+
+pub static SAND_SCRIPT: &str = include_str!("scripts/sand.rhai");
+
+
+/// Grid state shared with the functions registered on the engine while a tick runs.
+#[derive(Default)]
+struct ScriptState {
+    voxels: Voxels,
+    width: usize,
+    height: usize,
+    length: usize,
+    /// Voxel the script is currently running for, `None` once it has been removed.
+    current: Option<(usize, usize, usize)>,
+    /// Cells that received a voxel this tick, so a voxel isn't processed twice.
+    visited: Vec<bool>,
+    changed: bool,
+}
+
+impl ScriptState {
+    fn offset(&self, dx: INT, dy: INT, dz: INT) -> Option<(usize, usize, usize)> {
+        let (x, y, z) = self.current?;
+        let target = (x as INT + dx, y as INT + dy, z as INT + dz);
+
+        if target.0 < 0 || target.0 >= self.width as INT || target.1 < 0 || target.1 >= self.height as INT || target.2 < 0 || target.2 >= self.length as INT {
+            return None;
+        }
+
+        Some((target.0 as usize, target.1 as usize, target.2 as usize))
+    }
+
+    fn index(&self, (x, y, z): (usize, usize, usize)) -> usize {
+        (x * self.height + y) * self.length + z
+    }
+
+    fn swap(&mut self, target: (usize, usize, usize)) {
+        let current = self.current.unwrap();
+        let (a, b) = (self.voxels[current.0][current.1][current.2], self.voxels[target.0][target.1][target.2]);
+
+        self.voxels[current.0][current.1][current.2] = b;
+        self.voxels[target.0][target.1][target.2] = a;
+
+        let (i, j) = (self.index(current), self.index(target));
+        self.visited[i] = b.is_some();
+        self.visited[j] = true;
+
+        self.current = Some(target);
+        self.changed = true;
+    }
+}
+
+
+/// A per-voxel rule written in Rhai. The script defines `fn tick(x, y, z)`, which
+/// runs once per occupied voxel per update and can call:
+///
+/// - `in_bounds(dx, dy, dz)`, `is_empty(dx, dy, dz)` to inspect neighbors
+/// - `move_by(dx, dy, dz)` to move into an empty neighbor, `swap(dx, dy, dz)` to trade places with any neighbor
+/// - `set_color(r, g, b)`, `remove()` to transform the voxel
+/// - `random_int(n)` and `array.shuffle()` for randomness
+///
+/// Offsets are relative to the voxel, which follows any moves made during the call.
+pub struct VoxelScript {
+    pub source: String,
+    engine: Engine,
+    ast: AST,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+impl std::fmt::Debug for VoxelScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VoxelScript").field("source", &self.source).finish()
+    }
+}
+
+impl VoxelScript {
+    pub fn new(source: &str) -> Result<Self, Box<EvalAltResult>> {
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let mut engine = Engine::new();
+
+        // guard against scripts that never return
+        engine.set_max_operations(100_000);
+
+        let s = state.clone();
+        engine.register_fn("in_bounds", move |dx: INT, dy: INT, dz: INT| {
+            s.lock().unwrap().offset(dx, dy, dz).is_some()
+        });
+
+        let s = state.clone();
+        engine.register_fn("is_empty", move |dx: INT, dy: INT, dz: INT| {
+            let state = s.lock().unwrap();
+            state.offset(dx, dy, dz).is_some_and(|(x, y, z)| state.voxels[x][y][z].is_none())
+        });
+
+        let s = state.clone();
+        engine.register_fn("move_by", move |dx: INT, dy: INT, dz: INT| {
+            let mut state = s.lock().unwrap();
+            match state.offset(dx, dy, dz) {
+                Some((x, y, z)) if state.voxels[x][y][z].is_none() => {
+                    state.swap((x, y, z));
+                    true
+                },
+                _ => false
+            }
+        });
+
+        let s = state.clone();
+        engine.register_fn("swap", move |dx: INT, dy: INT, dz: INT| {
+            let mut state = s.lock().unwrap();
+            match state.offset(dx, dy, dz) {
+                Some(target) => {
+                    state.swap(target);
+                    true
+                },
+                None => false
+            }
+        });
+
+        let s = state.clone();
+        engine.register_fn("set_color", move |r: INT, g: INT, b: INT| {
+            let mut state = s.lock().unwrap();
+            if let Some((x, y, z)) = state.current {
+                state.voxels[x][y][z] = Some(Color32::from_rgb(r.clamp(0, 255) as u8, g.clamp(0, 255) as u8, b.clamp(0, 255) as u8));
+                state.changed = true;
+            }
+        });
+
+        let s = state.clone();
+        engine.register_fn("remove", move || {
+            let mut state = s.lock().unwrap();
+            if let Some((x, y, z)) = state.current.take() {
+                state.voxels[x][y][z] = None;
+                state.changed = true;
+            }
+        });
+
+        engine.register_fn("random_int", |n: INT| {
+            if n <= 0 { 0 } else { thread_rng().gen_range(0..n) }
+        });
+
+        engine.register_fn("shuffle", |array: &mut Array| {
+            array.shuffle(&mut thread_rng());
+        });
+
+        let ast = engine.compile(source)?;
+
+        if !ast.iter_functions().any(|f| f.name == "tick" && f.params.len() == 3) {
+            return Err("script must define `fn tick(x, y, z)`".into());
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            engine,
+            ast,
+            state,
+        })
+    }
+
+    pub fn sand() -> Self {
+        Self::new(SAND_SCRIPT).expect("Built-in sand script failed to compile")
+    }
+
+    /// Runs `tick` for every occupied voxel, bottom layer first. Returns whether anything changed.
+    /// On a runtime error the grid is left as the script had modified it so far.
+    pub fn run(&self, voxels: &mut Voxels, width: usize, height: usize, length: usize) -> Result<bool, Box<EvalAltResult>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.voxels = std::mem::take(voxels);
+            (state.width, state.height, state.length) = (width, height, length);
+            state.visited = vec![false; width * height * length];
+            state.changed = false;
+        }
+
+        let mut result = Ok(());
+
+        'outer: for y in 0..height {
+            for x in 0..width {
+                for z in 0..length {
+                    {
+                        let mut state = self.state.lock().unwrap();
+                        let index = state.index((x, y, z));
+                        if state.voxels[x][y][z].is_none() || state.visited[index] {
+                            continue;
+                        }
+                        state.current = Some((x, y, z));
+                    }
+
+                    let mut scope = Scope::new();
+                    if let Err(err) = self.engine.call_fn::<Dynamic>(&mut scope, &self.ast, "tick", (x as INT, y as INT, z as INT)) {
+                        result = Err(err);
+                        break 'outer;
+                    }
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        *voxels = std::mem::take(&mut state.voxels);
+        state.current = None;
+
+        result.map(|_| state.changed)
+    }
+}
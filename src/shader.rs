@@ -1,84 +1,129 @@
-use std::sync::MutexGuard;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
-use eframe::glow::{self, HasContext};
-use eframe::egui::Vec2;
-use nalgebra::{Vector2, Vector3, Vector4};
-use rand;
+use eframe::glow;
+use nalgebra::Vector4;
 
-use crate::{camera::Camera, mesh::Mesh};
+use crate::render_queue::{Blend, Geometry, RenderQueue, Uniform};
+use crate::shader_error::{ShaderError, ShaderStage};
+use crate::{camera::Camera, gpu, lighting::Lighting, shadow::ShadowMap, voxel_manager::VOXEL_WIDTH};
 
 
 pub struct ShaderProgram {
     pub program : glow::Program,
-    vert_shader: glow::Shader,
-    frag_shader: glow::Shader
+    vs_path: String,
+    fs_path: String,
+    /// Why the last build from the source files failed. The program then runs the bundled
+    /// shaders, or keeps its previous version after a failed `reload`.
+    pub error: Option<ShaderError>,
+    locations: RefCell<HashMap<&'static str, Option<glow::UniformLocation>>>,
 }
 
 
 impl ShaderProgram {
+    /// Builds the program from its source files, falling back to the copies bundled into
+    /// the binary when that fails.
     pub fn new(gl: &glow::Context, vs_path: &str, fs_path: &str) -> Self {
-        use glow::HasContext as _;
+        match Self::try_new(gl, vs_path, fs_path) {
+            Ok(program) => program,
+            Err(err) => {
+                log::error!("{err}");
+                let sources = (bundled_source(vs_path), bundled_source(fs_path));
+                let (Some(vs), Some(fs)) = sources else {
+                    panic!("{err}");
+                };
+                let mut program = Self::from_sources(gl, (vs_path, vs), (fs_path, fs)).unwrap_or_else(|err| panic!("{err}"));
+                program.error = Some(err);
+                program
+            }
+        }
+    }
 
-        unsafe {
-            let program = gl.create_program().expect("Cannot create program");
+    /// Builds the program from its source files. Natively they're read from disk if present,
+    /// so edits are picked up without rebuilding, and from the bundled copies otherwise.
+    pub fn try_new(gl: &glow::Context, vs_path: &str, fs_path: &str) -> Result<Self, ShaderError> {
+        let vs = load_source(ShaderStage::Vertex, vs_path)?;
+        let fs = load_source(ShaderStage::Fragment, fs_path)?;
 
-            
-            #[cfg(not(target_arch = "wasm32"))] 
-            let (vertex_shader_source, fragment_shader_source) = 
-            (
-                std::fs::read_to_string(vs_path).unwrap(),
-                std::fs::read_to_string(fs_path).unwrap(),
-            );
+        Self::from_sources(gl, (vs_path, &vs), (fs_path, &fs))
+    }
 
+    fn from_sources(gl: &glow::Context, (vs_path, vs): (&str, &str), (fs_path, fs): (&str, &str)) -> Result<Self, ShaderError> {
+        use glow::HasContext as _;
 
-            #[cfg(target_arch = "wasm32")] 
-            let (vertex_shader_source, fragment_shader_source) = 
-            (
-                VERT_SHADER,
-                FRAG_SHADER,
-            );
+        unsafe {
+            let program = gl.create_program().expect("Cannot create program");
+            gpu::track_created(1);
 
             let shader_sources = [
-                (glow::VERTEX_SHADER, vertex_shader_source),
-                (glow::FRAGMENT_SHADER, fragment_shader_source),
+                (glow::VERTEX_SHADER, ShaderStage::Vertex, vs, vs_path),
+                (glow::FRAGMENT_SHADER, ShaderStage::Fragment, fs, fs_path),
             ];
 
-            let shaders: Vec<_> = shader_sources
-            .iter()
-            .map(|(shader_type, shader_source)| {
+            let mut shaders = Vec::new();
+            let mut error = None;
+            for (shader_type, stage, shader_source, path) in shader_sources {
                 let shader = gl
-                    .create_shader(*shader_type)
+                    .create_shader(shader_type)
                     .expect("Cannot create shader");
-                gl.shader_source(shader, &format!("{shader_source}"));
-                gl.compile_shader(shader);
-                assert!(
-                    gl.get_shader_compile_status(shader),
-                    "Failed to compile {shader_type}: {}",
-                    gl.get_shader_info_log(shader)
-                );
+                shaders.push(shader);
                 gl.attach_shader(program, shader);
-                shader
-            })
-            .collect();
+                gl.shader_source(shader, shader_source);
+                gl.compile_shader(shader);
+                if !gl.get_shader_compile_status(shader) {
+                    error = Some(ShaderError::from_log(stage, path, &gl.get_shader_info_log(shader)));
+                    break;
+                }
+            }
 
 
             // assert status of the shader
-            gl.link_program(program);
-            assert!(
-                gl.get_program_link_status(program),
-                "{}",
-                gl.get_program_info_log(program)
-            );
+            if error.is_none() {
+                gl.link_program(program);
+                if !gl.get_program_link_status(program) {
+                    error = Some(ShaderError::from_log(ShaderStage::Link, &format!("{vs_path} + {fs_path}"), &gl.get_program_info_log(program)));
+                }
+            }
 
             for shader in shaders.iter() {
                 gl.detach_shader(program, *shader);
                 gl.delete_shader(*shader);
             }
 
-            Self {
+            let x = Self {
                 program,
-                vert_shader: shaders[0],
-                frag_shader: shaders[1]
+                vs_path: vs_path.to_string(),
+                fs_path: fs_path.to_string(),
+                error: None,
+                locations: RefCell::default(),
+            };
+
+            match error {
+                Some(err) => {
+                    x.destroy(gl);
+                    Err(err)
+                },
+                None => Ok(x),
+            }
+        }
+    }
+
+    /// Source files the program was built from.
+    pub fn paths(&self) -> [&str; 2] {
+        [&self.vs_path, &self.fs_path]
+    }
+
+    /// Rebuilds the program from its source files. On failure the current program is kept.
+    pub fn reload(&mut self, gl: &glow::Context) -> Result<(), ShaderError> {
+        match Self::try_new(gl, &self.vs_path, &self.fs_path) {
+            Ok(program) => {
+                self.destroy(gl);
+                *self = program;
+                Ok(())
+            },
+            Err(err) => {
+                self.error = Some(err.clone());
+                Err(err)
             }
         }
     }
@@ -89,40 +134,109 @@ impl ShaderProgram {
         unsafe {
             gl.delete_program(self.program);
         }
+        gpu::track_deleted(1);
+    }
+
+    /// Location of a uniform, looked up once per program.
+    // only copyable natively, web locations are objects
+    #[allow(clippy::clone_on_copy)]
+    fn location(&self, gl: &glow::Context, name: &'static str) -> Option<glow::UniformLocation> {
+        use glow::HasContext as _;
+
+        self.locations.borrow_mut()
+            .entry(name)
+            .or_insert_with(|| unsafe { gl.get_uniform_location(self.program, name) })
+            .clone()
+    }
+
+    /// Sets a uniform of the program, which must be in use. Names the program doesn't use
+    /// are ignored.
+    pub fn set_uniform(&self, gl: &glow::Context, name: &'static str, value: impl Into<Uniform>) {
+        use glow::HasContext as _;
+
+        let location = self.location(gl, name);
+        let location = location.as_ref();
+        unsafe {
+            match value.into() {
+                Uniform::F32(x) => gl.uniform_1_f32(location, x),
+                Uniform::I32(x) => gl.uniform_1_i32(location, x),
+                Uniform::Vec3(x) => gl.uniform_3_f32(location, x.x, x.y, x.z),
+                Uniform::Vec4(x) => gl.uniform_4_f32(location, x.x, x.y, x.z, x.w),
+                Uniform::Mat4(x) => gl.uniform_matrix_4_f32_slice(location, false, x.as_slice()),
+            }
+        }
     }
 
-    pub fn paint(&self, gl: &glow::Context, mesh: &Mesh, ghost: &Option<Mesh>, bounding_box: &Mesh, camera: &Camera) {
+    /// Renders the shadow map from the queue's shadow casters, then draws every item.
+    pub fn paint(&self, gl: &glow::Context, queue: &RenderQueue, camera: &Camera, lighting: &Lighting, shadow_map: &ShadowMap) {
         use glow::HasContext as _;
 
+        let (min, max) = queue.bounds;
+        let light_view_proj = ShadowMap::light_view_proj(lighting, min, max);
+
+        if lighting.shadows {
+            shadow_map.render(gl, queue, &light_view_proj);
+        }
+
+        let frame: [(&'static str, Uniform); 8] = [
+            ("u_ViewProj", camera.get_proj_view_mat().into()),
+            ("u_VoxelWidth", VOXEL_WIDTH.into()),
+            ("u_LightDir", lighting.direction().into()),
+            ("u_Ambient", lighting.ambient.into()),
+            ("u_AoStrength", lighting.ambient_occlusion.into()),
+            ("u_LightViewProj", light_view_proj.into()),
+            ("u_Shadows", lighting.shadows.into()),
+            ("u_ShadowMap", 0.into()),
+        ];
+
         unsafe {
             
             gl.clear(glow::DEPTH_BUFFER_BIT);
             gl.depth_func(glow::LESS);
-            gl.enable(glow::DEPTH_TEST);
 
             gl.use_program(Some(self.program));
-
-            gl.uniform_matrix_4_f32_slice(
-                gl.get_uniform_location(self.program, "u_ViewProj").as_ref(),
-                false, 
-                camera.get_proj_view_mat().as_slice()
-            );
-
-            gl.bind_vertex_array(Some(bounding_box.vertex_array));
-            gl.draw_elements(glow::LINES, bounding_box.index_buffer_size as i32, glow::UNSIGNED_INT, 0);
-            
-            match ghost {
-                Some(x) => {
-                    // println!("Painting Ghost");
-                    gl.bind_vertex_array(Some(x.vertex_array));
-                    gl.draw_elements(glow::TRIANGLES, x.index_buffer_size as i32, glow::UNSIGNED_INT, 0);        
-                },
-                None => ()
+            for (name, value) in frame {
+                self.set_uniform(gl, name, value);
+            }
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(shadow_map.depth_texture));
+
+            for item in queue.ordered() {
+                match item.blend {
+                    Blend::Opaque => gl.disable(glow::BLEND),
+                    Blend::Alpha => {
+                        gl.enable(glow::BLEND);
+                        gl.blend_func(glow::ONE, glow::ONE_MINUS_SRC_ALPHA);
+                    },
+                    Blend::Additive => {
+                        gl.enable(glow::BLEND);
+                        gl.blend_func(glow::ONE, glow::ONE);
+                    },
+                }
+                if item.depth_test { gl.enable(glow::DEPTH_TEST) } else { gl.disable(glow::DEPTH_TEST) }
+                gl.depth_mask(item.depth_write);
+
+                self.set_uniform(gl, "u_Model", item.model);
+                self.set_uniform(gl, "u_Tint", Vector4::from(item.tint.to_array()));
+                self.set_uniform(gl, "u_Instanced", matches!(item.geometry, Geometry::Instances(_)));
+                for (name, value) in item.uniforms.iter() {
+                    self.set_uniform(gl, name, *value);
+                }
+
+                item.geometry.draw(gl, item.primitive);
+
+                // later items see the frame's values again, and nothing of this item's own
+                for (name, value) in item.uniforms.iter() {
+                    match frame.iter().find(|(frame_name, _)| frame_name == name) {
+                        Some((_, frame_value)) => self.set_uniform(gl, name, *frame_value),
+                        None => self.set_uniform(gl, name, value.zero()),
+                    }
+                }
             }
 
-
-            gl.bind_vertex_array(Some(mesh.vertex_array));
-            gl.draw_elements(if mesh.wireframe {glow::LINES} else {glow::TRIANGLES}, mesh.index_buffer_size as i32, glow::UNSIGNED_INT, 0);
+            gl.disable(glow::BLEND);
+            gl.depth_mask(true);
+            gl.enable(glow::DEPTH_TEST);
         }
     }
 }
@@ -136,20 +250,42 @@ precision mediump float;
 layout(location = 0) in vec4 vs_pos;
 layout(location = 1) in vec4 vs_col;
 layout(location = 2) in vec2 vs_uv;
+layout(location = 3) in vec3 vs_normal;
+layout(location = 4) in float vs_occlusion;
+layout(location = 5) in vec3 vs_offset;
+layout(location = 6) in vec4 vs_instance_col;
 
 out vec4 fs_col;
 out vec2 fs_uv; 
 out vec3 fs_pos;
+out vec3 fs_normal;
+out float fs_occlusion;
+out vec4 fs_light_pos;
 
 uniform mat4 u_ViewProj;
+uniform float u_VoxelWidth;
+uniform mat4 u_LightViewProj;
+// instances of a unit cube, placed and colored per instance
+uniform bool u_Instanced;
+// per draw, in voxel units
+uniform mat4 u_Model;
+uniform vec4 u_Tint;
 
 void main() {
     // fs_col = vs_col;
-    fs_col = vs_col;
+    fs_col = (u_Instanced ? vs_instance_col : vs_col) * u_Tint;
     fs_uv = vs_uv;
+    fs_normal = vs_normal;
+    fs_occlusion = vs_occlusion;
 
     vec4 pos = vs_pos;
+    if (u_Instanced) {
+        pos.xyz += vs_offset;
+    }
+    pos = u_Model * pos;
+    pos.xyz *= u_VoxelWidth;
     pos.y *= -1.0;
+    fs_light_pos = u_LightViewProj * pos;
 
     pos =  u_ViewProj * pos;
     // pos.z = 0.0;
@@ -169,14 +305,119 @@ precision mediump float;
 in vec3 fs_pos;
 in vec4 fs_col;
 in vec2 fs_uv;
+in vec3 fs_normal;
+in float fs_occlusion;
+in highp vec4 fs_light_pos;
 out vec4 frag_color;
 
+// direction towards the light, in voxel space (y up)
+uniform vec3 u_LightDir;
+uniform float u_Ambient;
+// darkening of a fully occluded corner
+uniform float u_AoStrength;
+uniform highp sampler2DShadow u_ShadowMap;
+uniform bool u_Shadows;
+
+// fraction of a 3x3 neighborhood of shadow map taps that sees the light
+float visibility() {
+    vec3 p = fs_light_pos.xyz / fs_light_pos.w * 0.5 + 0.5;
+    if (!u_Shadows || p.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(u_ShadowMap, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(u_ShadowMap, vec3(p.xy + vec2(x, y) * texel, p.z - 0.0005));
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
     // frag_color = vec4(fs_uv, 0, 1);  // Sample the texture
     // frag_color = vec4(1 - fs_uv, 0, 1);
     // frag_color = vec4(fs_col.xyz, 1.0);
     // frag_color = vec4(1.0, 1.0, 1.0, 1.0);
     // frag_color  = vec4(gl_FragCoord.z);
-    frag_color = fs_col;
+    // geometry without normals (bounding box, ghost) is drawn unlit
+    float light = 1.0;
+    if (dot(fs_normal, fs_normal) > 0.0) {
+        light = u_Ambient + (1.0 - u_Ambient) * max(dot(normalize(fs_normal), u_LightDir), 0.0) * visibility();
+    }
+    light *= 1.0 - u_AoStrength * fs_occlusion / 3.0;
+    frag_color = vec4(fs_col.rgb * light, fs_col.a);
+}
+"#;
+
+#[cfg(target_arch = "wasm32")]
+const SHADOW_VERT_SHADER : &str = r#"#version 300 es
+precision highp float;
+
+layout(location = 0) in vec4 vs_pos;
+layout(location = 5) in vec3 vs_offset;
+
+uniform mat4 u_LightViewProj;
+uniform float u_VoxelWidth;
+uniform bool u_Instanced;
+uniform mat4 u_Model;
+
+void main() {
+    vec4 pos = vs_pos;
+    if (u_Instanced) {
+        pos.xyz += vs_offset;
+    }
+    pos = u_Model * pos;
+    pos.xyz *= u_VoxelWidth;
+    pos.y *= -1.0;
+
+    gl_Position = u_LightViewProj * pos;
+}
+"#;
+
+#[cfg(target_arch = "wasm32")]
+const SHADOW_FRAG_SHADER : &str = r#"#version 300 es
+precision mediump float;
+
+void main() {
+    // only depth is written
+}
+"#;
+
+/// GLSL ES versions of the shader files, which aren't on disk on the web.
+#[cfg(target_arch = "wasm32")]
+fn bundled_source(path: &str) -> Option<&'static str> {
+    match path {
+        "src/main.vert.glsl" => Some(VERT_SHADER),
+        "src/main.frag.glsl" => Some(FRAG_SHADER),
+        "src/shadow.vert.glsl" => Some(SHADOW_VERT_SHADER),
+        "src/shadow.frag.glsl" => Some(SHADOW_FRAG_SHADER),
+        _ => None,
+    }
+}
+
+/// The shader files as they were at build time, so the app runs from any directory.
+#[cfg(not(target_arch = "wasm32"))]
+fn bundled_source(path: &str) -> Option<&'static str> {
+    match path {
+        "src/main.vert.glsl" => Some(include_str!("main.vert.glsl")),
+        "src/main.frag.glsl" => Some(include_str!("main.frag.glsl")),
+        "src/shadow.vert.glsl" => Some(include_str!("shadow.vert.glsl")),
+        "src/shadow.frag.glsl" => Some(include_str!("shadow.frag.glsl")),
+        _ => None,
+    }
+}
+
+fn load_source(stage: ShaderStage, path: &str) -> Result<String, ShaderError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    match std::fs::read_to_string(path) {
+        Ok(source) => return Ok(source),
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(ShaderError::unreadable(stage, path, err)),
+        Err(_) => {},
+    }
+
+    bundled_source(path)
+        .map(str::to_string)
+        .ok_or_else(|| ShaderError::unreadable(stage, path, "no such file, and no bundled copy"))
 }
-"#;
\ No newline at end of file
@@ -0,0 +1,120 @@
+use std::fmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Link,
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShaderStage::Vertex => "vertex shader",
+            ShaderStage::Fragment => "fragment shader",
+            ShaderStage::Link => "program",
+        })
+    }
+}
+
+
+/// One line of a compiler or linker log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderMessage {
+    /// Source line the message points at, when the driver gives one.
+    pub line: Option<u32>,
+    pub text: String,
+}
+
+
+/// Why a shader program couldn't be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderError {
+    pub stage: ShaderStage,
+    /// Source file of the stage, both files joined by ` + ` for link errors.
+    pub path: String,
+    pub messages: Vec<ShaderMessage>,
+}
+
+impl ShaderError {
+    /// Error with the info log of a failed compile or link.
+    pub fn from_log(stage: ShaderStage, path: &str, log: &str) -> Self {
+        Self {
+            stage,
+            path: path.to_string(),
+            messages: parse_info_log(log),
+        }
+    }
+
+    /// Error for a source file that couldn't be read.
+    pub fn unreadable(stage: ShaderStage, path: &str, err: impl fmt::Display) -> Self {
+        Self {
+            stage,
+            path: path.to_string(),
+            messages: vec![ShaderMessage { line: None, text: err.to_string() }],
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = if self.stage == ShaderStage::Link { "link" } else { "build" };
+        write!(f, "Failed to {action} {} {}", self.stage, self.path)?;
+
+        for message in self.messages.iter() {
+            match message.line {
+                Some(line) => write!(f, "\n  {}:{line}: {}", self.path, message.text)?,
+                None => write!(f, "\n  {}", message.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+
+/// Splits an info log into messages, picking out line numbers in the formats of the common
+/// drivers: `0:12(5): error: ...` (Mesa), `0(12) : error C0000: ...` (NVIDIA) and
+/// `ERROR: 0:12: ...` (ANGLE and most others).
+pub fn parse_info_log(log: &str) -> Vec<ShaderMessage> {
+    log.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match locate(line) {
+            Some((number, rest)) => ShaderMessage { line: Some(number), text: rest.to_string() },
+            None => ShaderMessage { line: None, text: line.to_string() },
+        })
+        .collect()
+}
+
+/// Line number of a log line and the message following it, keeping the severity.
+fn locate(line: &str) -> Option<(u32, String)> {
+    let (severity, rest) = ["ERROR: ", "WARNING: "].iter()
+        .find_map(|prefix| line.strip_prefix(prefix).map(|rest| (prefix.trim_end_matches(": ").to_lowercase(), rest)))
+        .unwrap_or((String::new(), line));
+
+    // source string index, always 0 for a single source
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    let (number, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        split_number(rest)?
+    } else {
+        let (number, rest) = split_number(rest.strip_prefix('(')?)?;
+        (number, rest.strip_prefix(')')?)
+    };
+
+    // Mesa adds a column in parentheses
+    let rest = match rest.strip_prefix('(') {
+        Some(column) => column.split_once(')')?.1,
+        None => rest,
+    };
+    let text = rest.trim_start_matches([':', ' ']);
+
+    Some((number, if severity.is_empty() { text.to_string() } else { format!("{severity}: {text}") }))
+}
+
+fn split_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use web_time::{Duration, Instant};
+
+
+/// Polls the modification times of shader source files, so programs can be rebuilt while
+/// the app runs. Native only, the web build embeds its shaders.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+impl ShaderWatcher {
+    /// Minimum time between two looks at the files.
+    pub const INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new<P: Into<PathBuf>>(paths: impl IntoIterator<Item = P>) -> Self {
+        let files = paths.into_iter().map(|path| {
+            let path = path.into();
+            let modified = Self::modified(&path);
+            (path, modified)
+        }).collect();
+
+        Self {
+            files,
+            last_check: Instant::now(),
+        }
+    }
+
+    fn modified(path: &PathBuf) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /// Whether any file was written since the last call. Looks at most once per `INTERVAL`.
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        self.changed()
+    }
+
+    /// Whether any file was written since the last call, without waiting for `INTERVAL`.
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+
+        for (path, modified) in self.files.iter_mut() {
+            let current = Self::modified(path);
+            // a missing file shows up as a change too, so the error gets reported
+            if current != *modified {
+                *modified = current;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
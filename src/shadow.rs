@@ -0,0 +1,127 @@
+use eframe::glow::{self, HasContext as _};
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::{gpu, lighting::Lighting, render_queue::{Geometry, RenderQueue}, shader::ShaderProgram, voxel_manager::VOXEL_WIDTH};
+
+
+/// Depth of the voxel meshes as seen from the directional light, rendered into an
+/// offscreen framebuffer before the main pass samples it.
+pub struct ShadowMap {
+    pub shader: ShaderProgram,
+    pub framebuffer: glow::Framebuffer,
+    pub depth_texture: glow::Texture,
+    pub size: i32,
+}
+
+
+impl ShadowMap {
+    pub const SIZE: i32 = 2048;
+
+    pub fn new(gl: &glow::Context) -> Self {
+        let shader = ShaderProgram::new(gl, "src/shadow.vert.glsl", "src/shadow.frag.glsl");
+        let size = Self::SIZE;
+
+        unsafe {
+            let depth_texture = gl.create_texture().expect("Cannot create shadow map texture");
+            gl.bind_texture(glow::TEXTURE_2D, Some(depth_texture));
+            gl.tex_image_2d(glow::TEXTURE_2D, 0, glow::DEPTH_COMPONENT24 as i32, size, size, 0, glow::DEPTH_COMPONENT, glow::UNSIGNED_INT, glow::PixelUnpackData::Slice(None));
+            // linear filtering with depth comparison gives 2x2 PCF per tap for free
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_COMPARE_MODE, glow::COMPARE_REF_TO_TEXTURE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_COMPARE_FUNC, glow::LEQUAL as i32);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            let framebuffer = gl.create_framebuffer().expect("Cannot create shadow map framebuffer");
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::DEPTH_ATTACHMENT, glow::TEXTURE_2D, Some(depth_texture), 0);
+            gl.draw_buffers(&[glow::NONE]);
+            gl.read_buffer(glow::NONE);
+            assert_eq!(
+                gl.check_framebuffer_status(glow::FRAMEBUFFER),
+                glow::FRAMEBUFFER_COMPLETE,
+                "Shadow map framebuffer is incomplete"
+            );
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+            gpu::track_created(2);
+
+            Self {
+                shader,
+                framebuffer,
+                depth_texture,
+                size,
+            }
+        }
+    }
+
+    /// Orthographic view from the light that fits the box `min..max`, given in voxel units.
+    /// Works in the same world space as the main pass, so y is flipped and scaled by `VOXEL_WIDTH`.
+    pub fn light_view_proj(lighting: &Lighting, min: Vector3<f32>, max: Vector3<f32>) -> Matrix4<f32> {
+        let to_world = |p: Vector3<f32>| Vector3::new(p.x, -p.y, p.z) * VOXEL_WIDTH;
+        let (min, max) = (to_world(min), to_world(max));
+
+        let center = (min + max) / 2.0;
+        let radius = ((max - min).norm() / 2.0).max(VOXEL_WIDTH);
+        let dir = to_world(lighting.direction()).normalize();
+
+        // looking straight down needs a different up vector
+        let up = if dir.y.abs() > 0.99 { Vector3::x() } else { Vector3::y() };
+        let eye = center + dir * radius * 2.0;
+        let view = Matrix4::look_at_rh(&Point3::from(eye), &Point3::from(center), &up);
+        let proj = Matrix4::new_orthographic(-radius, radius, -radius, radius, radius * 0.5, radius * 3.5);
+
+        proj * view
+    }
+
+    /// Renders the depth of the queue's shadow casters. Restores the framebuffer, viewport and
+    /// scissor test egui had set up.
+    pub fn render(&self, gl: &glow::Context, queue: &RenderQueue, light_view_proj: &Matrix4<f32>) {
+        unsafe {
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            let mut viewport = [0; 4];
+            gl.get_parameter_i32_slice(glow::VIEWPORT, &mut viewport);
+            let scissor = gl.is_enabled(glow::SCISSOR_TEST);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.viewport(0, 0, self.size, self.size);
+            gl.disable(glow::SCISSOR_TEST);
+            gl.enable(glow::DEPTH_TEST);
+            gl.depth_func(glow::LESS);
+            gl.depth_mask(true);
+            gl.clear(glow::DEPTH_BUFFER_BIT);
+
+            // push depths back a little to keep faces from shadowing themselves
+            gl.enable(glow::POLYGON_OFFSET_FILL);
+            gl.polygon_offset(2.0, 4.0);
+
+            gl.use_program(Some(self.shader.program));
+            self.shader.set_uniform(gl, "u_LightViewProj", *light_view_proj);
+            self.shader.set_uniform(gl, "u_VoxelWidth", VOXEL_WIDTH);
+
+            for item in queue.shadow_casters() {
+                self.shader.set_uniform(gl, "u_Model", item.model);
+                self.shader.set_uniform(gl, "u_Instanced", matches!(item.geometry, Geometry::Instances(_)));
+                item.geometry.draw(gl, item.primitive);
+            }
+
+            gl.disable(glow::POLYGON_OFFSET_FILL);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+            gl.viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            if scissor {
+                gl.enable(glow::SCISSOR_TEST);
+            }
+        }
+    }
+
+    pub fn destroy(&self, gl: &glow::Context) {
+        self.shader.destroy(gl);
+        unsafe {
+            gl.delete_framebuffer(self.framebuffer);
+            gl.delete_texture(self.depth_texture);
+        }
+        gpu::track_deleted(2);
+    }
+}
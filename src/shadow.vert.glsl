@@ -0,0 +1,21 @@
+#version 330
+
+layout(location = 0) in vec4 vs_pos;
+layout(location = 5) in vec3 vs_offset;
+
+uniform mat4 u_LightViewProj;
+uniform float u_VoxelWidth;
+uniform bool u_Instanced;
+uniform mat4 u_Model;
+
+void main() {
+    vec4 pos = vs_pos;
+    if (u_Instanced) {
+        pos.xyz += vs_offset;
+    }
+    pos = u_Model * pos;
+    pos.xyz *= u_VoxelWidth;
+    pos.y *= -1.0;
+
+    gl_Position = u_LightViewProj * pos;
+}
@@ -1,16 +1,162 @@
-use crate::mesh::Mesh;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+
+use crate::bit_grid::{BitGrid, Mirror};
+use crate::coloring::{ColorVariation, MAX_COMPACTION};
+use crate::mesh_data::{MeshData, VoxelInstance};
+use crate::rules::{SandRule, VoxelRule};
+use crate::script::VoxelScript;
 use egui::Color32;
-use nalgebra::{Vector2, Vector3, Vector4, VectorView3};
-use rand::thread_rng;
+use nalgebra::Vector3;
+use rand::{thread_rng, Rng};
 use rand::seq::SliceRandom;
 
 pub static VOXEL_WIDTH : f32 = 0.2;
 
+/// Grid contents indexed `[x][y][z]`.
+pub type Voxels = Vec<Vec<Vec<Option<Color32>>>>;
+
+
+/// Cells that changed, so only the chunks around them are remeshed. Past `limit` cells, or
+/// after changes nobody listed, the list is dropped and everything counts as changed.
+#[derive(Debug, Clone)]
+pub struct Changes {
+    cells: Vec<[usize; 3]>,
+    all: bool,
+    limit: usize,
+}
+
+impl Changes {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            cells: Vec::new(),
+            all: false,
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, x: usize, y: usize, z: usize) {
+        if self.all {
+            return;
+        }
+        if self.cells.len() >= self.limit {
+            self.mark_all();
+        } else {
+            self.cells.push([x, y, z]);
+        }
+    }
+
+    pub fn mark_all(&mut self) {
+        self.all = true;
+        self.cells = Vec::new();
+    }
+
+    /// The changed cells, possibly repeated, or `None` when everything counts as changed.
+    pub fn cells(&self) -> Option<&[[usize; 3]]> {
+        (!self.all).then_some(&self.cells[..])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.cells.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshMode {
+    /// Two triangles for every exposed voxel face.
+    #[default]
+    Faces,
+    /// Coplanar adjacent faces of the same color are merged into larger quads.
+    Greedy,
+    /// Smoothed isosurface of the occupancy field, extracted with naive surface nets.
+    Smooth,
+}
+
+/// What newly spawned voxels are made of. Translucent materials have colors with alpha
+/// below 255 and are drawn blended over the opaque geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Material {
+    #[default]
+    Sand,
+    Water,
+    Glass,
+}
+
+impl Material {
+    pub const ALL: [Material; 3] = [Material::Sand, Material::Water, Material::Glass];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Material::Sand => "Sand",
+            Material::Water => "Water",
+            Material::Glass => "Glass",
+        }
+    }
+
+    pub fn colors(&self) -> Vec<Color32> {
+        match self {
+            Material::Sand => VoxelManager::colors(),
+            Material::Water => vec![
+                Color32::from_rgba_unmultiplied(0x3a, 0x8d, 0xde, 140),
+                Color32::from_rgba_unmultiplied(0x33, 0x80, 0xd4, 140),
+            ],
+            Material::Glass => vec![Color32::from_rgba_unmultiplied(0xd8, 0xf0, 0xf0, 70)],
+        }
+    }
+
+    /// Tint applied on top of the palette when meshing.
+    pub fn variation(&self) -> ColorVariation {
+        match self {
+            Material::Sand => ColorVariation {
+                noise: 0.1,
+                noise_scale: 3.0,
+                floor_color: Color32::from_hex("#a9855a").unwrap(),
+                gradient: 0.4,
+                compaction: 0.03,
+            },
+            Material::Water => ColorVariation {
+                noise: 0.05,
+                noise_scale: 6.0,
+                floor_color: Color32::from_hex("#17477d").unwrap(),
+                gradient: 0.6,
+                compaction: 0.0,
+            },
+            Material::Glass => ColorVariation { noise: 0.04, noise_scale: 2.0, ..ColorVariation::NONE },
+        }
+    }
+
+    /// Material whose palette holds `color`, if any.
+    pub fn of(color: Color32) -> Option<Material> {
+        static PALETTES: LazyLock<Vec<(Material, Vec<Color32>)>> = LazyLock::new(|| {
+            Material::ALL.iter().map(|material| (*material, material.colors())).collect()
+        });
+
+        PALETTES.iter().find(|(_, colors)| colors.contains(&color)).map(|(material, _)| *material)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct VoxelManager {
-    pub voxels: Vec<Vec<Vec<Option<Color32>>>>,
+    /// Only changed through `set_voxel` and the other methods here, so `packed` stays in sync.
+    voxels: Voxels,
     pub length: usize,
     pub width: usize,
-    pub height: usize
+    pub height: usize,
+    pub rule: Arc<dyn VoxelRule>,
+    /// Number of updates run so far.
+    pub tick: usize,
+    /// Replaces `rule` when set.
+    pub script: Option<Arc<VoxelScript>>,
+    /// Bit-packed copy of `voxels`. When set, `update` runs the sand rule on it instead of
+    /// `rule` and mirrors the moves back into `voxels`.
+    packed: Option<BitGrid>,
+    /// Cells changed since `take_changes`.
+    changes: Changes,
+    pub mesh_mode: MeshMode,
+    /// Strength of each material's procedural tint, 0 keeps the spawn colors.
+    pub color_variation: f32,
+    /// Runtime error of the last script, which is unloaded when it fails.
+    pub script_error: Option<String>
 }
 
 impl VoxelManager {
@@ -27,211 +173,573 @@ impl VoxelManager {
     
 
     pub fn new(length: usize, width: usize, height: usize) -> Self{
-        let voxels : Vec<Vec<Vec<Option<Color32>>>> = (0..width).into_iter().map(|x| {
-            (0..height).into_iter().map(|y| {
-                (0..length).map(|z| None).collect()
-            }).collect()
-        }).collect();
+        let voxels : Voxels = vec![vec![vec![None; length]; height]; width];
 
         Self {
             voxels,
             length,
             width,
-            height
+            height,
+            rule: Arc::new(SandRule),
+            tick: 0,
+            script: None,
+            packed: None,
+            changes: Changes::new(Self::change_limit(length, width, height)),
+            mesh_mode: MeshMode::default(),
+            color_variation: 0.0,
+            script_error: None
         }
     }
 
-    pub fn update(&mut self) -> bool {
-        let mut changed = false;
-
-        for y in 0..self.height {
-            for x in 0..self.width {
-                for z in 0..self.length {
-                    if self.voxels[x][y][z].is_none() {
-                        continue;
+    /// Changes the grid dimensions. With `preserve`, voxels inside the overlap of the
+    /// old and new box are kept in place, anything outside is dropped.
+    pub fn resize(&mut self, length: usize, width: usize, height: usize, preserve: bool) {
+        let mut resized = Self::new(length, width, height);
+        resized.rule = self.rule.clone();
+        resized.tick = self.tick;
+        resized.script = self.script.take();
+        resized.mesh_mode = self.mesh_mode;
+        resized.color_variation = self.color_variation;
+        resized.script_error = self.script_error.take();
+
+        if preserve {
+            for x in 0..width.min(self.width) {
+                for y in 0..height.min(self.height) {
+                    for z in 0..length.min(self.length) {
+                        resized.voxels[x][y][z] = self.voxels[x][y][z];
                     }
+                }
+            }
+        }
 
-                    if y != 0 && self.voxels[x][y-1][z].is_none() {
-                        let color  = self.voxels[x][y][z].unwrap();
-                        self.voxels[x][y][z] = None;
-                        self.voxels[x][y-1][z] = Some(color);  
-                        changed = true;
-                    } else {
-                        let mut offsets: Vec<(i32, i32, i32)> = vec![
-                            (1, -1, 0),
-                            (-1, -1, 0),
-                            (0, -1, 1),
-                            (0, -1, -1),
-                            (1, -1, 1),
-                            (1, -1, -1),
-                            (-1, -1, 1),
-                            (-1, -1, -1),
-                        ];
-
-                        offsets.shuffle(&mut thread_rng());
-                        
-                        for offset in offsets.iter() {
-                            let target: (i32, i32, i32) = (x as i32 + offset.0, y as i32 + offset.1, z as i32 + offset.2);
-                            if target.0 < 0 || target.0 >= self.width as i32 ||  target.1 < 0 || target.1 >= self.height as i32 || target.2 < 0 || target.2 >= self.length as i32 {
-                                continue;
-                            }
+        let packed = self.packed.is_some();
+        *self = resized;
+        self.changes.mark_all();
+        self.set_packed(packed);
+    }
 
-                            if self.voxels[target.0 as usize][target.1 as usize][target.2 as usize].is_none() {
-                                let color = self.voxels[x][y][z].unwrap();
-                                self.voxels[x][y][z] = None;
-                                self.voxels[target.0 as usize][target.1 as usize][target.2 as usize] = Some(color);
-                                changed = true;
-                                break;
-                            }
-                        }
-                    }
+    /// Copy of the grid and mesh settings, without the simulation state, for meshing elsewhere.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            voxels: self.voxels.clone(),
+            length: self.length,
+            width: self.width,
+            height: self.height,
+            rule: self.rule.clone(),
+            tick: self.tick,
+            script: None,
+            packed: None,
+            changes: Changes::new(self.changes.limit),
+            mesh_mode: self.mesh_mode,
+            color_variation: self.color_variation,
+            script_error: None
+        }
+    }
+
+    pub fn voxels(&self) -> &Voxels {
+        &self.voxels
+    }
+
+    pub fn get_voxel(&self, x: usize, y: usize, z: usize) -> Option<Color32> {
+        self.voxels[x][y][z]
+    }
+
+    pub fn packed(&self) -> Option<&BitGrid> {
+        self.packed.as_ref()
+    }
+
+    pub fn set_packed(&mut self, enabled: bool) {
+        self.packed = enabled.then(|| BitGrid::from_voxels(&self.voxels, self.length, self.width, self.height));
+    }
+
+    pub fn set_voxel(&mut self, x: usize, y: usize, z: usize, voxel: Option<Color32>) {
+        self.voxels[x][y][z] = voxel;
+        self.changes.push(x, y, z);
+        if let Some(packed) = self.packed.as_mut() {
+            packed.set(x, y, z, voxel);
+        }
+    }
+
+    /// Replaces the whole grid, which must have the same dimensions, and returns the old one.
+    pub fn set_voxels(&mut self, voxels: Voxels) -> Voxels {
+        assert_eq!((voxels.len(), voxels.first().map_or(0, |c| c.len())), (self.width, self.height), "Grid size doesn't match");
+        let old = std::mem::replace(&mut self.voxels, voxels);
+        self.changes.mark_all();
+        self.set_packed(self.packed.is_some());
+        old
+    }
+
+    /// Returns the cells changed since the last call, by any means, and starts a new list.
+    pub fn take_changes(&mut self) -> Changes {
+        let fresh = Changes::new(self.changes.limit);
+        std::mem::replace(&mut self.changes, fresh)
+    }
+
+    /// Past this many changes, diffing the grid is about as cheap as going through the list.
+    fn change_limit(length: usize, width: usize, height: usize) -> usize {
+        (length * width * height / 8).max(64)
+    }
+
+    /// Layer new sand is spawned into.
+    pub fn spawn_layer(&self) -> usize {
+        self.height - 1
+    }
+
+    pub fn update(&mut self) -> bool {
+        let tick = self.tick;
+        self.tick += 1;
+
+        if let Some(script) = self.script.clone() {
+            // scripts don't say what they moved
+            self.changes.mark_all();
+            return match script.run(&mut self.voxels, self.width, self.height, self.length) {
+                Ok(changed) => changed,
+                Err(err) => {
+                    self.script_error = Some(err.to_string());
+                    self.script = None;
+                    true
                 }
-            }
+            };
+        }
+
+        if let Some(packed) = self.packed.as_mut() {
+            return packed.update(Some(Mirror { voxels: &mut self.voxels, changes: &mut self.changes }));
         }
-        changed
+
+        self.rule.clone().step(&mut self.voxels, tick, &mut self.changes)
     }
 
-    pub fn get_mesh(&self, gl: &eframe::glow::Context ) -> Mesh{
-        let mut verts: Vec<Vector3<f32>> = Vec::new();
-        let mut colors: Vec<Color32> = Vec::new();
+    /// Fills every cell with sand with the given probability, for seeding automata like Life.
+    pub fn randomize(&mut self, density: f64) {
+        let colors = Self::colors();
+        let mut rng = thread_rng();
 
-        for x in 0..self.width {
-            for z in 0..self.length {
-                for y in 0..self.height {
-                    if self.voxels[x][y][z].is_none() {
-                        continue;
-                    }
+        self.voxels.iter_mut().flatten().flatten().for_each(|voxel| {
+            *voxel = rng.gen_bool(density.clamp(0.0, 1.0)).then(|| *colors.choose(&mut rng).unwrap());
+        });
+
+        self.changes.mark_all();
+        self.set_packed(self.packed.is_some());
+    }
+
+    pub fn get_mesh(&self) -> MeshData {
+        self.get_mesh_region([0, 0, 0], [self.width, self.height, self.length])
+    }
+
+    /// Mesh of the voxels with `min <= [x, y, z] < max`. Neighbors outside the region
+    /// are still checked, so regions can be meshed separately and drawn together.
+    pub fn get_mesh_region(&self, min: [usize; 3], max: [usize; 3]) -> MeshData {
+        match self.mesh_mode {
+            MeshMode::Faces => self.get_face_mesh_region(min, max),
+            MeshMode::Greedy => self.get_greedy_mesh_region(min, max),
+            MeshMode::Smooth => self.get_smooth_mesh_region(min, max),
+        }
+    }
+
+    pub fn get_face_mesh(&self) -> MeshData {
+        self.get_face_mesh_region([0, 0, 0], [self.width, self.height, self.length])
+    }
 
-                    let color = self.voxels[x][y][z].unwrap();
+    pub fn get_face_mesh_region(&self, min: [usize; 3], max: [usize; 3]) -> MeshData {
+        self.faces_where(min, max, |_| true)
+    }
+
+    /// Face mesh of the voxels in the region whose color passes `keep`.
+    fn faces_where(&self, min: [usize; 3], max: [usize; 3], keep: impl Fn(Color32) -> bool) -> MeshData {
+        let mut mesh = MeshData::default();
+
+        for x in min[0]..max[0] {
+            for z in min[2]..max[2] {
+                for y in min[1]..max[1] {
+                    let Some(color) = self.voxels[x][y][z].filter(|&c| keep(c)) else {
+                        continue;
+                    };
+                    let voxel = [x, y, z];
                     // println!("Found a true at {:?}", (x, y, z));
 
 
-                    if (x+1 < self.width && self.voxels[x+1][y][z].is_none()) || x+1 == self.width{
+                    if (x+1 < self.width && !self.hides(color, self.voxels[x+1][y][z])) || x+1 == self.width{
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x + 1.0, y, z),
                             Vector3::new(x + 1.0, y + 1.0, z),
                             Vector3::new(x + 1.0, y, z + 1.0),
-                            
-                            Vector3::new(x + 1.0, y + 1.0, z),
                             Vector3::new(x + 1.0, y + 1.0, z + 1.0),
-                            Vector3::new(x + 1.0, y, z + 1.0),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 0, 1, color);
                     }
 
-                    if (x > 0 && self.voxels[x-1][y][z].is_none()) || x == 0 {
+                    if (x > 0 && !self.hides(color, self.voxels[x-1][y][z])) || x == 0 {
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x, y, z),
                             Vector3::new(x, y + 1.0, z),
                             Vector3::new(x, y, z + 1.0),
-                            
-                            Vector3::new(x, y + 1.0, z),
                             Vector3::new(x, y + 1.0, z + 1.0),
-                            Vector3::new(x, y, z + 1.0),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 0, -1, color);
                     }
 
-                    if (y > 0 && self.voxels[x][y-1][z].is_none()) || y == 0 {
+                    if (y > 0 && !self.hides(color, self.voxels[x][y-1][z])) || y == 0 {
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x, y, z),
                             Vector3::new(x + 1.0, y, z),
                             Vector3::new(x, y, z + 1.0),
-                            
-                            Vector3::new(x + 1.0, y, z),
                             Vector3::new(x + 1.0, y, z + 1.0),
-                            Vector3::new(x, y, z + 1.0),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 1, -1, color);
                     }
 
-                    if (y+1 < self.height && self.voxels[x][y+1][z].is_none()) || y+1 == self.height{
+                    if (y+1 < self.height && !self.hides(color, self.voxels[x][y+1][z])) || y+1 == self.height{
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x, y + 1.0, z),
                             Vector3::new(x + 1.0, y + 1.0, z),
                             Vector3::new(x, y + 1.0, z + 1.0),
-                            
-                            Vector3::new(x + 1.0, y + 1.0, z),
                             Vector3::new(x + 1.0, y + 1.0, z + 1.0),
-                            Vector3::new(x, y + 1.0, z + 1.0),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 1, 1, color);
                     }
 
-                    if (z+1 < self.length && self.voxels[x][y][z+1].is_none()) || z+1 == self.length{
+                    if (z+1 < self.length && !self.hides(color, self.voxels[x][y][z+1])) || z+1 == self.length{
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x, y, z + 1.0),
                             Vector3::new(x , y + 1.0, z+ 1.0),
                             Vector3::new(x + 1.0, y, z + 1.0),
-                            
-                            Vector3::new(x, y + 1.0, z + 1.0),
                             Vector3::new(x + 1.0, y + 1.0, z + 1.0),
-                            Vector3::new(x + 1.0, y, z + 1.0),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 2, 1, color);
                     }
 
-                    if (z > 0 && self.voxels[x][y][z-1].is_none()) || z == 0 {
+                    if (z > 0 && !self.hides(color, self.voxels[x][y][z-1])) || z == 0 {
                         let (x, y, z) = (x as f32, y as f32, z as f32);
-                        verts.append(&mut vec![
+                        self.push_face(&mut mesh, voxel, [
                             Vector3::new(x, y, z),
                             Vector3::new(x, y + 1.0, z),
                             Vector3::new(x + 1.0, y, z),
-                            
-                            Vector3::new(x, y + 1.0, z),
                             Vector3::new(x + 1.0, y + 1.0, z),
-                            Vector3::new(x + 1.0, y, z),
-                        ]);
-                        (0..6).for_each(|_| colors.push(color));
+                        ], 2, -1, color);
                     }
                 }
             }
         }
 
-        verts.iter_mut().for_each(|x| *x = *x * VOXEL_WIDTH);
+        mesh
+    }
 
-        let count = verts.len();
+    /// Contents at `p`, with everything outside the grid empty.
+    fn voxel(&self, p: [i32; 3]) -> Option<Color32> {
+        let dims = [self.width, self.height, self.length];
+        if (0..3).any(|i| p[i] < 0 || p[i] >= dims[i] as i32) {
+            return None;
+        }
+        self.voxels[p[0] as usize][p[1] as usize][p[2] as usize]
+    }
 
-        let uvs = (0..count).into_iter().map(|x| {
-            Vector2::new(0.0, 0.0)
-        }).collect();
-        
-        Mesh::new(gl, verts, (0..count).into_iter().map(|x| x as u32).collect(), uvs, false, colors)
+    /// Color of the voxel at `p` after its material's procedural tint.
+    pub fn tint(&self, color: Color32, p: [usize; 3]) -> Color32 {
+        if self.color_variation == 0.0 {
+            return color;
+        }
+        let Some(material) = Material::of(color) else {
+            return color;
+        };
+
+        let height = p[1] as f32 / (self.height.max(2) - 1) as f32;
+        let stacked = (p[1] + 1..self.height.min(p[1] + 1 + MAX_COMPACTION))
+            .take_while(|&y| self.voxels[p[0]][y][p[2]].is_some())
+            .count();
+
+        material.variation().apply(color, p, height, stacked, self.color_variation)
+    }
+
+    /// Whether `neighbor` covers the face of a voxel of `color` touching it. Translucent
+    /// voxels only hide faces of the same material, so solids stay visible through water
+    /// while the inside of a pool isn't drawn.
+    fn hides(&self, color: Color32, neighbor: Option<Color32>) -> bool {
+        neighbor.is_some_and(|n| n.a() == 255 || n == color || Material::of(n).is_some_and(|m| Material::of(color) == Some(m)))
     }
 
+    /// Occlusion level of a face corner, counting the two side neighbors and the diagonal
+    /// neighbor in front of the face. Two sides fully enclose the corner.
+    fn corner_occlusion(&self, voxel: [usize; 3], d: usize, dir: i32, corner: Vector3<f32>) -> u8 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        let mut front = voxel.map(|c| c as i32);
+        front[d] += dir;
+
+        let side = |axis: usize| if corner[axis] > voxel[axis] as f32 { 1 } else { -1 };
+        let mut a = front;
+        a[u] += side(u);
+        let mut b = front;
+        b[v] += side(v);
+        let mut diagonal = a;
+        diagonal[v] += side(v);
+
+        // only opaque voxels cast occlusion
+        let opaque = |p: [i32; 3]| self.voxel(p).is_some_and(|c| c.a() == 255);
+        let (a, b, diagonal) = (opaque(a), opaque(b), opaque(diagonal));
+        if a && b { 3 } else { a as u8 + b as u8 + diagonal as u8 }
+    }
 
-    pub fn get_bounding_box(&self, gl: &eframe::glow::Context ) -> Mesh{
-        let (width, height, length) = (self.width as f32, self.height as f32, self.length as f32);
+    fn face_occlusion(&self, voxel: [usize; 3], d: usize, dir: i32, corners: [Vector3<f32>; 4]) -> [u8; 4] {
+        corners.map(|corner| self.corner_occlusion(voxel, d, dir, corner))
+    }
+
+    /// Face of `voxel` looking along `dir` on axis `d`.
+    fn push_face(&self, mesh: &mut MeshData, voxel: [usize; 3], corners: [Vector3<f32>; 4], d: usize, dir: i32, color: Color32) {
+        let mut normal = Vector3::zeros();
+        normal[d] = dir as f32;
+
+        mesh.push_quad(corners, normal, self.tint(color, voxel), self.face_occlusion(voxel, d, dir, corners));
+    }
+
+    /// Sweeps each axis slice by slice, building a mask of exposed faces and greedily
+    /// growing runs with the same color and occlusion first along one in-plane axis, then the other.
+    /// Runs merge on spawn colors; the tint is taken at the voxels in the quad's corners and
+    /// blended across it, so color variation doesn't split them.
+    pub fn get_greedy_mesh(&self) -> MeshData {
+        self.get_greedy_mesh_region([0, 0, 0], [self.width, self.height, self.length])
+    }
+
+    pub fn get_greedy_mesh_region(&self, min: [usize; 3], max: [usize; 3]) -> MeshData {
+        let mut mesh = MeshData::default();
+
+        let voxel = |p: [i32; 3]| self.voxel(p);
+
+        for d in 0..3 {
+            let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+            let (size_u, size_v) = (max[u] - min[u], max[v] - min[v]);
+            let mut mask: Vec<Option<(Color32, [u8; 4])>> = vec![None; size_u * size_v];
+
+            for dir in [-1, 1] {
+                for slice in min[d]..max[d] {
+                    let plane = (slice as i32 + if dir > 0 { 1 } else { 0 }) as f32;
+                    let corner_at = |a: usize, b: usize| {
+                        let mut p = Vector3::zeros();
+                        (p[d], p[u], p[v]) = (plane, (min[u] + a) as f32, (min[v] + b) as f32);
+                        p
+                    };
+
+                    for a in 0..size_u {
+                        for b in 0..size_v {
+                            let mut p = [0; 3];
+                            (p[d], p[u], p[v]) = (slice as i32, (min[u] + a) as i32, (min[v] + b) as i32);
+                            let color = voxel(p);
+
+                            let mut front = p;
+                            front[d] += dir;
+                            mask[a * size_v + b] = match color {
+                                Some(color) if !self.hides(color, voxel(front)) => {
+                                    let corners = [corner_at(a, b), corner_at(a + 1, b), corner_at(a, b + 1), corner_at(a + 1, b + 1)];
+                                    let p = p.map(|c| c as usize);
+                                    Some((color, self.face_occlusion(p, d, dir, corners)))
+                                },
+                                _ => None,
+                            };
+                        }
+                    }
+
+                    for a in 0..size_u {
+                        let mut b = 0;
+                        while b < size_v {
+                            let Some(face) = mask[a * size_v + b] else {
+                                b += 1;
+                                continue;
+                            };
+
+                            let mut h = 1;
+                            while b + h < size_v && mask[a * size_v + b + h] == Some(face) {
+                                h += 1;
+                            }
+
+                            let mut w = 1;
+                            while a + w < size_u && (b..b + h).all(|k| mask[(a + w) * size_v + k] == Some(face)) {
+                                w += 1;
+                            }
+
+                            for i in a..a + w {
+                                mask[i * size_v + b..i * size_v + b + h].fill(None);
+                            }
+
+                            let (color, occlusion) = face;
+                            let corner = |du: usize, dv: usize| corner_at(a + du, b + dv);
+
+                            let mut normal = Vector3::zeros();
+                            normal[d] = dir as f32;
+
+                            let tint = |du: usize, dv: usize| {
+                                let mut p = [0; 3];
+                                (p[d], p[u], p[v]) = (slice, min[u] + a + du, min[v] + b + dv);
+                                self.tint(color, p)
+                            };
+                            let colors = [tint(0, 0), tint(w - 1, 0), tint(0, h - 1), tint(w - 1, h - 1)];
+
+                            mesh.push_shaded_quad([corner(0, 0), corner(w, 0), corner(0, h), corner(w, h)], normal, colors, occlusion);
+
+                            b += h;
+                        }
+                    }
+                }
+            }
+        }
 
-        let mut verts = cube_wireframe_from_points(Vector3::new(0.0, 0.0, 0.0), Vector3::new(width, height, length));
+        mesh
+    }
 
-        verts.iter_mut().for_each(|x| *x = *x * VOXEL_WIDTH);
 
+    /// Every voxel with at least one exposed face, for instanced cube rendering. Like the
+    /// face mesh, the walls of the box don't hide faces. Instances aren't sorted, so the
+    /// renderer draws translucent voxels opaque.
+    pub fn get_instances(&self) -> Vec<VoxelInstance> {
+        let mut instances = Vec::new();
 
-        let uvs = (0..24).into_iter().map(|x| {
-            Vector2::new(0.0, 0.0)
+        for (x, column) in self.voxels.iter().enumerate() {
+            for (y, row) in column.iter().enumerate() {
+                for (z, voxel) in row.iter().enumerate() {
+                    let Some(color) = *voxel else {
+                        continue;
+                    };
+
+                    let p = [x as i32, y as i32, z as i32];
+                    let enclosed = (0..3).all(|d| [-1, 1].into_iter().all(|dir| {
+                        let mut neighbor = p;
+                        neighbor[d] += dir;
+                        self.hides(color, self.voxel(neighbor))
+                    }));
+
+                    if !enclosed {
+                        instances.push(VoxelInstance {
+                            position: [x as u16, y as u16, z as u16],
+                            color: self.tint(color, [x, y, z])
+                        });
+                    }
+                }
+            }
+        }
+
+        instances
+    }
+
+    /// Naive surface nets over a field sampled at voxel centers. Every cell of the dual grid
+    /// the surface passes through gets one vertex at the average of its crossing edges, and
+    /// every edge between an occupied and an empty sample becomes a quad joining the four
+    /// cells around it. Below the floor counts as solid so piles blend into it. Translucent
+    /// voxels count as empty and are meshed as faces, so they're still blended and sorted.
+    pub fn get_smooth_mesh(&self) -> MeshData {
+        self.get_smooth_mesh_region([0, 0, 0], [self.width, self.height, self.length])
+    }
+
+    /// Quads belong to the region holding their occupied sample. Vertices only depend on the
+    /// grid, so they line up with those of neighboring regions.
+    pub fn get_smooth_mesh_region(&self, min: [usize; 3], max: [usize; 3]) -> MeshData {
+        let mut mesh = MeshData::default();
+        let mut vertices: HashMap<[i32; 3], u32> = HashMap::new();
+
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    let p = [x as i32, y as i32, z as i32];
+                    if !self.solid(p) {
+                        continue;
+                    }
+
+                    for d in 0..3 {
+                        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+
+                        for dir in [-1, 1] {
+                            let mut neighbor = p;
+                            neighbor[d] += dir;
+                            if self.solid(neighbor) {
+                                continue;
+                            }
+
+                            let mut lower = p;
+                            lower[d] = p[d].min(neighbor[d]);
+
+                            let corners = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(a, b)| {
+                                let mut cell = lower;
+                                cell[u] -= 1 - a;
+                                cell[v] -= 1 - b;
+                                *vertices.entry(cell).or_insert_with(|| self.push_cell_vertex(&mut mesh, cell))
+                            });
+
+                            mesh.indicies.extend([0, 1, 2, 1, 3, 2].map(|i| corners[i]));
+                        }
+                    }
+                }
+            }
+        }
+
+        mesh.append(&self.faces_where(min, max, |c| c.a() < 255));
+        mesh
+    }
+
+    /// Occupancy used by the smooth mesh: opaque voxels, and everything below the floor.
+    fn solid(&self, p: [i32; 3]) -> bool {
+        p[1] < 0 || self.voxel(p).is_some_and(|c| c.a() == 255)
+    }
+
+    /// Adds the vertex of the dual cell whose corners are the voxel centers `cell..=cell + 1`
+    /// and returns its index.
+    fn push_cell_vertex(&self, mesh: &mut MeshData, cell: [i32; 3]) -> u32 {
+        let corner = |i: usize| [cell[0] + (i & 1) as i32, cell[1] + ((i >> 1) & 1) as i32, cell[2] + ((i >> 2) & 1) as i32];
+        let solid: [bool; 8] = std::array::from_fn(|i| self.solid(corner(i)));
+
+        let mut sum = Vector3::zeros();
+        let mut crossings = 0;
+        let mut gradient = Vector3::zeros();
+
+        for i in 0..8 {
+            for d in 0..3 {
+                let j = i | 1 << d;
+                if i & 1 << d != 0 {
+                    continue;
+                }
+
+                let (a, b) = (solid[i], solid[j]);
+                if a != b {
+                    let mid = (Vector3::from(corner(i).map(|c| c as f32)) + Vector3::from(corner(j).map(|c| c as f32))) / 2.0;
+                    sum += mid;
+                    crossings += 1;
+                }
+                gradient[d] += b as i32 as f32 - a as i32 as f32;
+            }
+        }
+
+        let colors: Vec<Color32> = (0..8).filter(|&i| solid[i]).filter_map(|i| {
+            let [x, y, z] = corner(i);
+            self.voxel([x, y, z]).map(|c| self.tint(c, [x as usize, y as usize, z as usize]))
         }).collect();
+        let n = colors.len().max(1) as u32;
+        let total = colors.iter().fold([0u32; 3], |acc, c| [acc[0] + c.r() as u32, acc[1] + c.g() as u32, acc[2] + c.b() as u32]);
+
+        // voxel centers sit half a unit into the voxel
+        mesh.positions.push(sum / crossings.max(1) as f32 + Vector3::repeat(0.5));
+        mesh.normals.push(if gradient.norm() > 0.0 { -gradient.normalize() } else { Vector3::y() });
+        mesh.colors.push(Color32::from_rgb((total[0] / n) as u8, (total[1] / n) as u8, (total[2] / n) as u8));
+        // lines up with the occlusion of translucent faces in the same mesh
+        mesh.occlusion.push(0);
+
+        (mesh.positions.len() - 1) as u32
+    }
+
+    pub fn get_bounding_box(&self) -> MeshData {
+        let (width, height, length) = (self.width as f32, self.height as f32, self.length as f32);
+
+        let verts = cube_wireframe_from_points(Vector3::new(0.0, 0.0, 0.0), Vector3::new(width, height, length));
 
-        Mesh::new(gl, verts, (0..24).into_iter().map(|x| x as u32).collect(), uvs, false, (0..24).into_iter().map(|_| Color32::WHITE).collect())
+        let count = verts.len();
+        MeshData::from_unindexed(verts, vec![Color32::WHITE; count])
     }
 
-    pub fn get_ghost_mesh(&self, gl: &eframe::glow::Context, pos: Vector3<f32>, dir: Vector3<f32>) -> (Option<Mesh>, Option<(usize, usize)>) {
+    pub fn get_ghost_mesh(&self, pos: Vector3<f32>, dir: Vector3<f32>) -> (Option<MeshData>, Option<(usize, usize)>) {
         let (mut minx, mut miny, mut minz, mut mindepth) = (u32::MAX, u32::MAX, u32::MAX, f32::MAX);
 
+        let spawn_layer = self.spawn_layer();
+
         for x in 0..self.width {
             for z in 0..self.length {
-                match self.ray_box_intersection(pos, dir, x as u32, -29, z as u32) {
-                    Some(depth) => {
-                        if depth < mindepth {
-                            (minx, miny, minz, mindepth) = (x as u32, 29, z as u32, depth)
-                        };
-                    },
-                    None => (),
+                if let Some(depth) = self.ray_box_intersection(pos, dir, x as u32, -(spawn_layer as i32), z as u32) {
+                    if depth < mindepth {
+                        (minx, miny, minz, mindepth) = (x as u32, spawn_layer as u32, z as u32, depth)
+                    };
                 }
             }
         }
@@ -242,13 +750,11 @@ impl VoxelManager {
         
         let (x, y, z) = (minx as f32, miny as f32, minz as f32);
 
-        let verts = cube_verts_from_points(Vector3::new(x * VOXEL_WIDTH, y * VOXEL_WIDTH, z * VOXEL_WIDTH), Vector3::new((x+1.0) * VOXEL_WIDTH, (y+1.0) * VOXEL_WIDTH, (z+1.0) * VOXEL_WIDTH));
+        let verts = cube_verts_from_points(Vector3::new(x, y, z), Vector3::new(x + 1.0, y + 1.0, z + 1.0));
 
-        let uvs = (0..36).into_iter().map(|x| {
-            Vector2::new(0.0, 0.0)
-        }).collect();
+        let count = verts.len();
 
-        (Some(Mesh::new(gl, verts, (0..36).into_iter().map(|x| x as u32).collect(), uvs, false, (0..36).into_iter().map(|_| Color32::WHITE).collect())), Some((x as usize, z as usize)))        // }
+        (Some(MeshData::from_unindexed(verts, vec![Color32::WHITE; count])), Some((x as usize, z as usize)))
     }
 
     pub fn ray_box_intersection(&self, pos: Vector3<f32>, dir: Vector3<f32>, x: u32, y: i32, z: u32) -> Option<f32> {
@@ -0,0 +1,128 @@
+use egui::Color32;
+use meshview::bit_grid::{BitGrid, Mirror};
+use meshview::voxel_manager::{Changes, VoxelManager};
+use proptest::prelude::*;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+fn settle(grid: &mut BitGrid) {
+    let limit = grid.count() * grid.height + 1;
+    let mut ticks = 0;
+    while grid.update(None) {
+        ticks += 1;
+        assert!(ticks <= limit, "simulation did not settle within {limit} ticks");
+    }
+}
+
+
+#[test]
+fn set_and_get_across_words() {
+    let mut grid = BitGrid::new(130, 2, 2);
+    for z in [0, 63, 64, 127, 129] {
+        grid.set(1, 1, z, Some(Color32::from_rgb(z as u8, 0, 0)));
+    }
+
+    assert_eq!(grid.count(), 5);
+    assert_eq!(grid.get(1, 1, 64), Some(Color32::from_rgb(64, 0, 0)));
+    assert_eq!(grid.get(1, 1, 65), None);
+
+    grid.set(1, 1, 64, None);
+    assert_eq!(grid.count(), 4);
+}
+
+#[test]
+fn long_row_falls_in_one_tick() {
+    let mut grid = BitGrid::new(100, 1, 3);
+    for z in 0..100 {
+        grid.set(0, 2, z, Some(SAND));
+    }
+
+    assert!(grid.update(None));
+    assert!((0..100).all(|z| grid.get(0, 1, z) == Some(SAND)));
+
+    settle(&mut grid);
+    assert!((0..100).all(|z| grid.get(0, 0, z) == Some(SAND)));
+}
+
+#[test]
+fn packed_manager_mirrors_voxels() {
+    let mut manager = VoxelManager::new(70, 4, 6);
+    manager.set_packed(true);
+    manager.set_voxel(1, 5, 66, Some(SAND));
+    manager.set_voxel(1, 4, 66, Some(SAND));
+
+    while manager.update() {}
+
+    let packed = manager.packed().unwrap();
+    assert_eq!(&packed.to_voxels(), manager.voxels());
+    assert_eq!(packed.count(), 2);
+    assert_eq!(manager.get_voxel(1, 0, 66), Some(SAND));
+
+    // moves made on the packed grid are listed for remeshing
+    let changes = manager.take_changes();
+    assert!(changes.cells().unwrap().contains(&[1, 0, 66]));
+}
+
+
+fn grid() -> impl Strategy<Value = BitGrid> {
+    (1usize..70, 1usize..5, 1usize..6).prop_flat_map(|(length, width, height)| {
+        prop::collection::vec(prop::bool::weighted(0.4), length * width * height).prop_map(move |cells| {
+            let mut grid = BitGrid::new(length, width, height);
+            for (i, filled) in cells.into_iter().enumerate() {
+                if filled {
+                    grid.set(i / (height * length), (i / length) % height, i % length, Some(SAND));
+                }
+            }
+            grid
+        })
+    })
+}
+
+proptest! {
+    #[test]
+    fn voxel_count_is_conserved(mut grid in grid(), ticks in 1usize..10) {
+        let before = grid.count();
+        for _ in 0..ticks {
+            grid.update(None);
+            prop_assert_eq!(grid.count(), before);
+        }
+    }
+
+    #[test]
+    fn mirror_tracks_every_move(mut grid in grid(), ticks in 1usize..10) {
+        let mut voxels = grid.to_voxels();
+        for _ in 0..ticks {
+            grid.update(Some(Mirror { voxels: &mut voxels, changes: &mut Changes::new(usize::MAX) }));
+            prop_assert_eq!(&grid.to_voxels(), &voxels);
+        }
+    }
+
+    #[test]
+    fn settles_like_sand_rule(mut grid in grid()) {
+        settle(&mut grid);
+        let settled = grid.to_voxels();
+
+        prop_assert!(!grid.update(None));
+
+        // every grain rests on another, and on all in-bounds diagonals below, like `SandRule`
+        for x in 0..grid.width {
+            for y in 1..grid.height {
+                for z in 0..grid.length {
+                    if settled[x][y][z].is_none() {
+                        continue;
+                    }
+                    for dx in -1i32..=1 {
+                        for dz in -1i32..=1 {
+                            let (nx, nz) = (x as i32 + dx, z as i32 + dz);
+                            if nx >= 0 && nx < grid.width as i32 && nz >= 0 && nz < grid.length as i32 {
+                                prop_assert!(settled[nx as usize][y - 1][nz as usize].is_some());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
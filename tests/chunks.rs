@@ -0,0 +1,141 @@
+use egui::Color32;
+use meshview::chunks::{Chunks, CHUNK_SIZE};
+use meshview::coloring::MAX_COMPACTION;
+use meshview::voxel_manager::{MeshMode, VoxelManager};
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+#[test]
+fn chunk_meshes_add_up_to_full_mesh() {
+    for mode in [MeshMode::Faces, MeshMode::Greedy] {
+        let mut manager = VoxelManager::new(40, 40, 20);
+        manager.mesh_mode = mode;
+        manager.randomize(0.3);
+
+        let mut chunks = Chunks::new(&manager);
+        let meshes = chunks.update(&mut manager);
+
+        assert_eq!(meshes.len(), 3 * 2 * 3);
+        if mode == MeshMode::Faces {
+            let triangles: usize = meshes.iter().map(|(_, mesh)| mesh.triangle_count()).sum();
+            assert_eq!(triangles, manager.get_mesh().triangle_count());
+        }
+    }
+}
+
+#[test]
+fn unchanged_grid_remeshes_nothing() {
+    let mut manager = VoxelManager::new(40, 40, 20);
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    assert!(chunks.update(&mut manager).is_empty());
+}
+
+#[test]
+fn change_only_dirties_touching_chunks() {
+    let mut manager = VoxelManager::new(48, 48, 48);
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    // interior of a chunk
+    manager.set_voxel(20, 20, 20, Some(SAND));
+    let dirty: Vec<usize> = chunks.update(&mut manager).into_iter().map(|(i, _)| i).collect();
+    assert_eq!(dirty.len(), 1);
+    let (min, max) = chunks.bounds(dirty[0]);
+    assert_eq!((min, max), ([16, 16, 16], [32, 32, 32]));
+
+    // on the boundary between two chunks along x
+    manager.set_voxel(CHUNK_SIZE - 1, 20, 20, Some(SAND));
+    assert_eq!(chunks.update(&mut manager).len(), 2);
+
+    // in a corner shared by eight chunks
+    manager.set_voxel(CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE, Some(SAND));
+    assert_eq!(chunks.update(&mut manager).len(), 8);
+}
+
+#[test]
+fn change_dirties_chunks_it_compacts() {
+    let mut manager = VoxelManager::new(48, 48, 48);
+    manager.color_variation = 1.0;
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    // sand stacked on a voxel near the top of the chunk below darkens it
+    manager.set_voxel(20, CHUNK_SIZE + 2, 20, Some(SAND));
+    let dirty: Vec<usize> = chunks.update(&mut manager).into_iter().map(|(i, _)| i).collect();
+    assert_eq!(dirty.len(), 2);
+    assert_eq!(chunks.bounds(dirty[0]).0, [16, 0, 16]);
+
+    // smooth vertices blend in the voxel above, which is compacted one cell further up
+    let mut manager = VoxelManager::new(16, 16, 32);
+    manager.color_variation = 1.0;
+    manager.mesh_mode = MeshMode::Smooth;
+    for y in 0..CHUNK_SIZE + MAX_COMPACTION {
+        manager.set_voxel(5, y, 5, Some(SAND));
+    }
+    let mut chunks = Chunks::new(&manager);
+    let mut meshes: Vec<_> = chunks.update(&mut manager).into_iter().map(|(_, mesh)| mesh).collect();
+
+    manager.set_voxel(5, CHUNK_SIZE + MAX_COMPACTION, 5, Some(SAND));
+    for (i, mesh) in chunks.update(&mut manager) {
+        meshes[i] = mesh;
+    }
+    for (i, mesh) in meshes.iter().enumerate() {
+        let (min, max) = chunks.bounds(i);
+        assert_eq!(*mesh, manager.get_mesh_region(min, max), "chunk {i} is stale");
+    }
+}
+
+#[test]
+fn rule_moves_only_dirty_touching_chunks() {
+    let mut manager = VoxelManager::new(48, 48, 48);
+    manager.set_voxel(20, 20, 20, Some(SAND));
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    // the grain falls one cell, inside its chunk
+    assert!(manager.update());
+    let dirty: Vec<usize> = chunks.update(&mut manager).into_iter().map(|(i, _)| i).collect();
+    assert_eq!(dirty.len(), 1);
+    assert_eq!(chunks.bounds(dirty[0]).0, [16, 16, 16]);
+}
+
+#[test]
+fn untracked_changes_dirty_every_chunk() {
+    let mut manager = VoxelManager::new(48, 48, 48);
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    manager.randomize(0.0);
+    assert!(manager.take_changes().cells().is_none());
+
+    // past the limit the list is dropped rather than growing with the grid
+    for x in 0..48 {
+        for y in 0..48 {
+            for z in 0..48 {
+                manager.set_voxel(x, y, z, Some(SAND));
+            }
+        }
+    }
+    assert_eq!(chunks.update(&mut manager).len(), chunks.len());
+}
+
+#[test]
+fn diff_finds_changes_that_were_not_listed() {
+    let mut manager = VoxelManager::new(48, 48, 48);
+    let mut chunks = Chunks::new(&manager);
+    chunks.update(&mut manager);
+
+    let mut voxels = manager.voxels().clone();
+    voxels[40][40][40] = Some(SAND);
+    let old = manager.set_voxels(voxels);
+    manager.take_changes();
+
+    chunks.diff(&old, &manager);
+    let dirty: Vec<usize> = chunks.remesh(&manager).into_iter().map(|(i, _)| i).collect();
+    assert_eq!(dirty.len(), 1);
+    assert_eq!(chunks.bounds(dirty[0]).0, [32, 32, 32]);
+}
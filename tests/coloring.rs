@@ -0,0 +1,91 @@
+use egui::Color32;
+use meshview::coloring::{value_noise, ColorVariation};
+use meshview::voxel_manager::{Material, VoxelManager};
+use nalgebra::Vector3;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+#[test]
+fn noise_is_repeatable_and_in_range() {
+    for i in 0..200 {
+        let p = Vector3::new(i as f32 * 0.37, i as f32 * 1.13, -(i as f32) * 0.71);
+        let n = value_noise(p);
+        assert!((0.0..=1.0).contains(&n));
+        assert_eq!(n, value_noise(p));
+    }
+    // continuous across lattice cells
+    assert!((value_noise(Vector3::new(1.999, 0.5, 0.5)) - value_noise(Vector3::new(2.001, 0.5, 0.5))).abs() < 0.01);
+}
+
+#[test]
+fn zero_strength_keeps_spawn_colors() {
+    let mut manager = VoxelManager::new(4, 4, 4);
+    manager.randomize(0.5);
+    let mesh = manager.get_face_mesh();
+    assert!(mesh.colors.iter().all(|c| VoxelManager::colors().contains(c)));
+
+    let variation = Material::Sand.variation();
+    assert_eq!(variation.apply(SAND, [1, 2, 3], 0.2, 5, 0.0), SAND);
+    assert_eq!(ColorVariation::NONE.apply(SAND, [1, 2, 3], 0.2, 5, 1.0), SAND);
+}
+
+#[test]
+fn buried_sand_is_darker() {
+    let mut manager = VoxelManager::new(1, 1, 10);
+    manager.color_variation = 1.0;
+    for y in 0..10 {
+        manager.set_voxel(0, y, 0, Some(SAND));
+    }
+
+    let brightness = |c: Color32| c.r() as u32 + c.g() as u32 + c.b() as u32;
+    assert!(brightness(manager.tint(SAND, [0, 0, 0])) < brightness(manager.tint(SAND, [0, 9, 0])));
+}
+
+#[test]
+fn tint_keeps_translucency() {
+    let mut manager = VoxelManager::new(1, 1, 4);
+    manager.color_variation = 1.0;
+    let water = Material::Water.colors()[0];
+
+    let tinted = manager.tint(water, [0, 0, 0]);
+    assert_ne!(tinted, water);
+    assert_eq!(tinted.a(), water.a());
+    assert_eq!(Material::of(water), Some(Material::Water));
+    assert_eq!(Material::of(Color32::RED), None);
+}
+
+#[test]
+fn variation_keeps_greedy_merging() {
+    let mut manager = VoxelManager::new(16, 16, 16);
+    manager.randomize(0.3);
+    let plain = manager.get_greedy_mesh();
+    manager.color_variation = 1.0;
+    let tinted = manager.get_greedy_mesh();
+
+    assert_eq!(tinted.triangle_count(), plain.triangle_count());
+    assert_ne!(tinted.colors, plain.colors);
+}
+
+#[test]
+fn greedy_column_blends_tint_from_floor_to_top() {
+    let mut manager = VoxelManager::new(1, 1, 10);
+    manager.color_variation = 1.0;
+    for y in 0..10 {
+        manager.set_voxel(0, y, 0, Some(SAND));
+    }
+
+    // one quad per side, shaded from the bottom voxel's tint to the top one's
+    let mesh = manager.get_greedy_mesh();
+    assert_eq!(mesh.triangle_count(), 6 * 2);
+    let (bottom, top) = (manager.tint(SAND, [0, 0, 0]), manager.tint(SAND, [0, 9, 0]));
+    assert_ne!(bottom, top);
+    for (position, color) in mesh.positions.iter().zip(&mesh.colors) {
+        match position.y {
+            0.0 => assert_eq!(*color, bottom),
+            10.0 => assert_eq!(*color, top),
+            y => panic!("vertex at height {y}"),
+        }
+    }
+}
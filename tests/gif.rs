@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use meshview::gif::{lzw_encode, quantize, GifEncoder};
+use meshview::recording::{Recorder, RecordingFormat, RecordingOutput, RecordingSettings};
+
+
+/// Reference GIF LZW decoder, widening codes once the table runs out of them.
+fn lzw_decode(bytes: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let (mut bit, mut width) = (0usize, min_code_size as usize + 1);
+    let mut table: HashMap<u16, Vec<u8>> = HashMap::new();
+    let mut next = end + 1;
+    let mut previous: Option<u16> = None;
+    let mut output = Vec::new();
+
+    loop {
+        let code = (0..width).fold(0u16, |code, i| {
+            let b = bit + i;
+            code | ((bytes[b / 8] >> (b % 8) & 1) as u16) << i
+        });
+        bit += width;
+
+        if code == clear {
+            table = (0..clear).map(|i| (i, vec![i as u8])).collect();
+            next = end + 1;
+            width = min_code_size as usize + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return output;
+        }
+
+        let entry = match (table.get(&code), previous) {
+            (Some(entry), _) => entry.clone(),
+            (None, Some(p)) if code == next => {
+                let mut entry = table[&p].clone();
+                entry.push(entry[0]);
+                entry
+            },
+            _ => panic!("invalid code {code}"),
+        };
+        output.extend(&entry);
+
+        if let Some(p) = previous {
+            if next < 4096 {
+                let mut added = table[&p].clone();
+                added.push(entry[0]);
+                table.insert(next, added);
+                next += 1;
+            }
+        }
+        previous = Some(code);
+        if next == 1 << width && width < 12 {
+            width += 1;
+        }
+    }
+}
+
+
+#[test]
+fn lzw_round_trips() {
+    let repetitive: Vec<u8> = (0..20_000).map(|i| (i / 7 % 5) as u8).collect();
+    let noisy: Vec<u8> = (0..50_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+
+    // every length up to past the first widenings, where the end code may need the wider width
+    for len in 0..1500 {
+        assert_eq!(lzw_decode(&lzw_encode(&noisy[..len], 8), 8), &noisy[..len]);
+    }
+    for indices in [vec![42], repetitive, noisy] {
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 8), 8), indices);
+    }
+}
+
+#[test]
+fn small_images_keep_their_colors() {
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 224, 171]];
+    let pixels: Vec<u8> = (0..64).flat_map(|i| {
+        let [r, g, b] = colors[i % 4];
+        [r, g, b, 255]
+    }).collect();
+
+    let (palette, indices) = quantize(&pixels);
+    assert_eq!(palette.len(), 4);
+    for (i, &index) in indices.iter().enumerate() {
+        let expected = colors[i % 4];
+        let actual = palette[index as usize];
+        assert!((0..3).all(|c| (expected[c] as i32 - actual[c] as i32).abs() <= 4), "{expected:?} became {actual:?}");
+    }
+}
+
+#[test]
+fn gif_has_a_block_per_frame() {
+    let mut encoder = GifEncoder::new(4, 2, 4);
+    let frame = vec![200; 4 * 2 * 4];
+    encoder.add_frame(&frame);
+    encoder.add_frame(&frame);
+    let bytes = encoder.finish();
+
+    assert!(bytes.starts_with(b"GIF89a"));
+    assert_eq!(bytes.last(), Some(&0x3b));
+    assert_eq!(bytes.windows(4).filter(|w| *w == [0x21, 0xf9, 4, 0]).count(), 2);
+}
+
+#[test]
+fn recorder_captures_every_nth_step() {
+    let settings = RecordingSettings { width: 2, height: 2, every: 3, format: RecordingFormat::PngSequence, ..Default::default() };
+    let mut recorder = Recorder::new(settings);
+
+    let captured: Vec<bool> = (0..7).map(|_| recorder.step()).collect();
+    assert_eq!(captured, [true, false, false, true, false, false, true]);
+
+    recorder.add_frame(&[0; 16]);
+    recorder.add_frame(&[255; 16]);
+    let RecordingOutput::PngSequence(files) = recorder.finish() else {
+        panic!("expected PNG files");
+    };
+    assert_eq!(files.len(), 2);
+}
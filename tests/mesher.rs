@@ -0,0 +1,73 @@
+use egui::Color32;
+use meshview::chunks::Chunks;
+use meshview::mesher::Mesher;
+use meshview::voxel_manager::VoxelManager;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+fn mesher(manager: &mut VoxelManager) -> Mesher {
+    let mut chunks = Chunks::new(manager);
+    chunks.remesh(manager);
+    Mesher::new(chunks, manager)
+}
+
+
+#[test]
+fn background_mesh_matches_chunk_remesh() {
+    let mut manager = VoxelManager::new(40, 40, 20);
+    let mut mesher = mesher(&mut manager);
+    manager.randomize(0.3);
+
+    let mut chunks = Chunks::new(&manager);
+    let expected = chunks.remesh(&manager);
+
+    mesher.request(&mut manager, true);
+    assert!(mesher.is_busy());
+    let batch = mesher.finish(&mut manager).unwrap();
+
+    assert!(!mesher.is_busy());
+    assert_eq!(batch.chunks, expected);
+}
+
+#[test]
+fn requests_while_busy_are_merged() {
+    let mut manager = VoxelManager::new(40, 40, 20);
+    let mut mesher = mesher(&mut manager);
+
+    manager.set_voxel(1, 1, 1, Some(SAND));
+    mesher.request(&mut manager, false);
+    manager.set_voxel(38, 1, 38, Some(SAND));
+    mesher.request(&mut manager, false);
+    manager.set_voxel(20, 1, 20, Some(SAND));
+    mesher.request(&mut manager, false);
+
+    // the first job and one merged follow-up, which sees the latest grid. Later results win.
+    let mut meshes = std::collections::HashMap::new();
+    for (i, mesh) in mesher.finish(&mut manager).unwrap().chunks {
+        meshes.insert(i, mesh);
+    }
+    let triangles: usize = meshes.values().map(|mesh| mesh.triangle_count()).sum();
+    assert_eq!(triangles, manager.get_mesh().triangle_count());
+    assert!(mesher.finish(&mut manager).is_none());
+}
+
+#[test]
+fn poll_eventually_returns_the_result() {
+    let mut manager = VoxelManager::new(16, 16, 16);
+    let mut mesher = mesher(&mut manager);
+    assert!(mesher.poll(&mut manager).is_none());
+
+    manager.set_voxel(3, 3, 3, Some(SAND));
+    mesher.request(&mut manager, false);
+
+    let batch = loop {
+        if let Some(batch) = mesher.poll(&mut manager) {
+            break batch;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(batch.chunks.len(), 1);
+    assert_eq!(batch.chunks[0].1.triangle_count(), 12);
+}
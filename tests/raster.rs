@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+
+use egui::Color32;
+use meshview::camera::Camera;
+use meshview::mesh_data::MeshData;
+use meshview::raster::{Image, Rasterizer};
+use meshview::voxel_manager::{Material, VoxelManager, VOXEL_WIDTH};
+use nalgebra::Vector3;
+
+
+const BACKGROUND: Color32 = Color32::from_rgb(20, 24, 30);
+
+fn camera_around(size: usize, r: f32, theta: f32, phi: f32) -> Camera {
+    let half = size as f32 * VOXEL_WIDTH / 2.0;
+    let mut camera = Camera::default();
+    camera.orbit(Vector3::new(half, -half, half), r, theta, phi);
+    camera
+}
+
+fn rasterizer(width: usize, height: usize) -> Rasterizer {
+    let mut rasterizer = Rasterizer::new(width, height);
+    rasterizer.background = BACKGROUND;
+    rasterizer
+}
+
+/// Pyramid of sand with a pool of water on one side.
+fn pile() -> VoxelManager {
+    let mut manager = VoxelManager::new(8, 8, 8);
+    let sand = Material::Sand.colors();
+    let water = Material::Water.colors();
+
+    for x in 0..8usize {
+        for z in 0..8usize {
+            let height = 4usize.saturating_sub(x.abs_diff(3).max(z.abs_diff(3)));
+            for y in 0..height {
+                manager.set_voxel(x, y, z, Some(sand[(x + y + z) % sand.len()]));
+            }
+            if height == 0 && x >= 6 {
+                manager.set_voxel(x, 0, z, Some(water[0]));
+            }
+        }
+    }
+
+    manager
+}
+
+/// Compares with a golden PNG in `tests/golden`, allowing small differences from float
+/// rounding. Run with `UPDATE_GOLDEN=1` to write the current output instead.
+fn assert_golden(name: &str, image: &Image) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, image.encode_png()).unwrap();
+        return;
+    }
+
+    let bytes = std::fs::read(&path).unwrap_or_else(|_| panic!("Missing {}, run with UPDATE_GOLDEN=1", path.display()));
+    let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+    let mut golden = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut golden).unwrap();
+    assert_eq!((info.width as usize, info.height as usize), (image.width, image.height));
+
+    // the golden file is straight alpha, like every PNG
+    let mut pixels = image.pixels.clone();
+    meshview::screenshot::unpremultiply(&mut pixels);
+
+    let differing = pixels.chunks_exact(4).zip(golden.chunks_exact(4))
+        .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 2))
+        .count();
+    assert!(differing <= image.width * image.height / 200, "{differing} pixels differ from {}", path.display());
+}
+
+
+#[test]
+fn empty_scene_is_background() {
+    let image = rasterizer(16, 8).render(&[], &Camera::default());
+    assert_eq!((image.width, image.height), (16, 8));
+    assert!(image.pixels.chunks_exact(4).all(|p| p == BACKGROUND.to_array()));
+}
+
+#[test]
+fn nearer_voxel_hides_farther_one() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.set_voxel(0, 1, 1, Some(Color32::RED));
+    manager.set_voxel(2, 1, 1, Some(Color32::BLUE));
+
+    // looking down -x, so the blue voxel at x = 2 is in front
+    let camera = camera_around(3, 3.0, 0.0, 0.0);
+    let image = rasterizer(32, 32).render(&[manager.get_face_mesh()], &camera);
+    let [r, g, b, a] = image.pixel(16, 16);
+    assert!(b > 0 && r == 0 && g == 0 && a == 255, "center is {:?}", [r, g, b, a]);
+
+    // and the same from the other side
+    let camera = camera_around(3, 3.0, 180.0, 0.0);
+    let image = rasterizer(32, 32).render(&[manager.get_face_mesh()], &camera);
+    let [r, g, b, _] = image.pixel(16, 16);
+    assert!(r > 0 && g == 0 && b == 0, "center is {:?}", [r, g, b]);
+}
+
+#[test]
+fn translucent_quad_blends_each_pixel_once() {
+    // unlit, half transparent quad facing the camera, split into two triangles
+    let mut mesh = MeshData::default();
+    let corner = |y: f32, z: f32| Vector3::new(0.0, y, z);
+    let color = Color32::from_rgba_premultiplied(0, 0, 128, 128);
+    mesh.push_quad([corner(-4.0, -4.0), corner(4.0, -4.0), corner(-4.0, 4.0), corner(4.0, 4.0)], Vector3::zeros(), color, [0; 4]);
+
+    let mut camera = Camera::default();
+    camera.orbit(Vector3::zeros(), 5.0, 0.0, 0.0);
+    let mut rasterizer = rasterizer(40, 40);
+    rasterizer.background = Color32::BLACK;
+    let image = rasterizer.render(&[mesh], &camera);
+
+    let covered: Vec<&[u8]> = image.pixels.chunks_exact(4).filter(|p| *p != Color32::BLACK.to_array()).collect();
+    assert!(covered.len() > 100);
+    // blending twice on the shared diagonal would make those pixels bluer
+    assert!(covered.iter().all(|p| *p == [0, 0, 128, 255]), "{:?}", covered.iter().find(|p| **p != [0, 0, 128, 255]));
+}
+
+#[test]
+fn floor_reaching_behind_the_camera_is_clipped() {
+    // one big quad, lit from straight above, reaching far behind the near plane
+    let mut mesh = MeshData::default();
+    let corner = |x: f32, z: f32| Vector3::new(x, 0.0, z);
+    mesh.push_quad([corner(-100.0, -100.0), corner(100.0, -100.0), corner(-100.0, 100.0), corner(100.0, 100.0)], Vector3::y(), Color32::WHITE, [0; 4]);
+
+    let mut camera = Camera::default();
+    camera.orbit(Vector3::zeros(), 2.0, 0.0, 30.0);
+    let mut rasterizer = rasterizer(24, 24);
+    rasterizer.lighting.elevation = 90.0;
+    let image = rasterizer.render(&[mesh], &camera);
+
+    // looking down at 30 degrees, so the floor fills the bottom and the background the top
+    for x in 0..24 {
+        assert_eq!(image.pixel(x, 23), [255; 4], "bottom row at {x}");
+        assert_eq!(image.pixel(x, 0), BACKGROUND.to_array(), "top row at {x}");
+    }
+}
+
+#[test]
+fn pile_matches_golden_image() {
+    let manager = pile();
+    let camera = camera_around(8, 2.5, 30.0, 35.0);
+    let image = rasterizer(96, 72).render(&[manager.get_face_mesh()], &camera);
+    assert_golden("pile_faces", &image);
+}
+
+#[test]
+fn greedy_pile_matches_golden_image() {
+    let manager = pile();
+    let camera = camera_around(8, 2.5, 210.0, 50.0);
+    let image = rasterizer(96, 72).render(&[manager.get_greedy_mesh()], &camera);
+    assert_golden("pile_greedy", &image);
+}
+
+#[test]
+fn greedy_and_face_meshes_render_alike() {
+    let manager = pile();
+    let camera = camera_around(8, 2.5, 30.0, 35.0);
+    let faces = rasterizer(96, 72).render(&[manager.get_face_mesh()], &camera);
+    let greedy = rasterizer(96, 72).render(&[manager.get_greedy_mesh()], &camera);
+
+    let differing = faces.pixels.chunks_exact(4).zip(greedy.pixels.chunks_exact(4))
+        .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 8))
+        .count();
+    assert!(differing < 96 * 72 / 50, "{differing} pixels differ");
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "gl")]
+
+use meshview::render_queue::{draw_order, shadow_order, Blend, Sorting, Uniform};
+use nalgebra::{Matrix4, Vector3};
+
+
+#[test]
+fn uniforms_convert_to_matching_types() {
+    assert_eq!(Uniform::from(true), Uniform::I32(1));
+    assert_eq!(Uniform::from(false), Uniform::I32(0));
+    assert_eq!(Uniform::from(0.5), Uniform::F32(0.5));
+    assert_eq!(Uniform::from(Vector3::x()), Uniform::Vec3(Vector3::x()));
+    assert_eq!(Uniform::from(Matrix4::<f32>::identity()), Uniform::Mat4(Matrix4::identity()));
+}
+
+#[test]
+fn items_are_opaque_by_default() {
+    assert_eq!(Blend::default(), Blend::Opaque);
+}
+
+fn sorting(blend: Blend, casts_shadow: bool, empty: bool) -> Sorting {
+    Sorting { blend, casts_shadow, empty }
+}
+
+#[test]
+fn opaque_items_draw_before_blended_ones() {
+    let items = [
+        sorting(Blend::Alpha, false, false),
+        sorting(Blend::Opaque, false, false),
+        sorting(Blend::Additive, false, false),
+        sorting(Blend::Opaque, false, false),
+    ];
+    assert_eq!(draw_order(&items), [1, 3, 0, 2]);
+}
+
+#[test]
+fn draw_order_keeps_submission_order() {
+    let opaque = [sorting(Blend::Opaque, false, false); 5];
+    assert_eq!(draw_order(&opaque), [0, 1, 2, 3, 4]);
+
+    let blended = [Blend::Additive, Blend::Alpha, Blend::Additive].map(|blend| sorting(blend, false, false));
+    assert_eq!(draw_order(&blended), [0, 1, 2]);
+}
+
+#[test]
+fn empty_items_are_skipped() {
+    let items = [
+        sorting(Blend::Opaque, true, true),
+        sorting(Blend::Alpha, true, false),
+        sorting(Blend::Alpha, false, true),
+        sorting(Blend::Opaque, true, false),
+    ];
+    assert_eq!(draw_order(&items), [3, 1]);
+    assert_eq!(shadow_order(&items), [1, 3]);
+    assert!(draw_order(&[]).is_empty());
+}
+
+#[test]
+fn only_shadow_casters_reach_the_shadow_map() {
+    let items = [
+        sorting(Blend::Opaque, false, false),
+        sorting(Blend::Alpha, true, false),
+        sorting(Blend::Opaque, true, false),
+    ];
+    // blended casters still cast, in submission order
+    assert_eq!(shadow_order(&items), [1, 2]);
+}
+
+#[test]
+fn uniforms_reset_to_zero_of_their_type() {
+    assert_eq!(Uniform::from(2.5).zero(), Uniform::F32(0.0));
+    assert_eq!(Uniform::from(true).zero(), Uniform::I32(0));
+    assert_eq!(Uniform::from(Matrix4::<f32>::identity()).zero(), Uniform::Mat4(Matrix4::zeros()));
+}
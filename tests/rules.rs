@@ -0,0 +1,98 @@
+use egui::Color32;
+use meshview::rules::{LifeRule, MargolusSandRule, Neighborhood, VoxelRule};
+use meshview::voxel_manager::VoxelManager;
+use std::sync::Arc;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+fn count(manager: &VoxelManager) -> usize {
+    manager.voxels().iter().flatten().flatten().filter(|v| v.is_some()).count()
+}
+
+
+#[test]
+fn life_notation_parses() {
+    let rule: LifeRule = "4555".parse().unwrap();
+    assert_eq!(rule, LifeRule::new(4..=5, 5..=5, Neighborhood::Moore));
+
+    let rule: LifeRule = "5,7,6,6".parse().unwrap();
+    assert_eq!(rule, LifeRule::new(5..=7, 6..=6, Neighborhood::Moore));
+
+    let rule: LifeRule = "1,2,1,1V".parse().unwrap();
+    assert_eq!(rule.neighborhood(), Neighborhood::VonNeumann);
+
+    assert!("455".parse::<LifeRule>().is_err());
+    assert!("45x5".parse::<LifeRule>().is_err());
+}
+
+#[test]
+fn neighborhood_sizes() {
+    assert_eq!(Neighborhood::VonNeumann.offsets().len(), 6);
+    assert_eq!(Neighborhood::Moore.offsets().len(), 26);
+}
+
+#[test]
+fn life_isolated_cell_dies() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.rule = Arc::new("4555".parse::<LifeRule>().unwrap());
+    manager.set_voxel(1, 1, 1, Some(SAND));
+
+    assert!(manager.update());
+    assert_eq!(count(&manager), 0);
+}
+
+#[test]
+fn life_birth_takes_neighbor_color() {
+    // von Neumann 1,6,2,2: a cell between two live cells is born
+    let mut manager = VoxelManager::new(3, 1, 1);
+    manager.rule = Arc::new("1,6,2,2V".parse::<LifeRule>().unwrap());
+    manager.set_voxel(0, 0, 0, Some(Color32::from_rgb(100, 0, 0)));
+    manager.set_voxel(0, 0, 2, Some(Color32::from_rgb(200, 0, 0)));
+
+    manager.update();
+    assert_eq!(manager.get_voxel(0, 0, 1), Some(Color32::from_rgb(150, 0, 0)));
+}
+
+#[test]
+fn life_birth_from_nothing_fills_empty_grid() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.rule = Arc::new("0,0,0,0".parse::<LifeRule>().unwrap());
+
+    assert!(manager.update());
+    assert_eq!(count(&manager), 27);
+    assert!(manager.voxels().iter().flatten().flatten().flatten().all(|c| VoxelManager::colors().contains(c)));
+}
+
+#[test]
+fn life_birth_range_from_zero_births_isolated_cells() {
+    // 1,1,0,0: isolated empty cells are born, cells next to one live cell are not
+    let mut manager = VoxelManager::new(3, 1, 1);
+    manager.rule = Arc::new("1,1,0,0V".parse::<LifeRule>().unwrap());
+    manager.set_voxel(0, 0, 0, Some(Color32::from_rgb(100, 0, 0)));
+
+    manager.update();
+    assert_eq!(manager.get_voxel(0, 0, 0), None);
+    assert_eq!(manager.get_voxel(0, 0, 1), None);
+    assert!(VoxelManager::colors().contains(&manager.get_voxel(0, 0, 2).unwrap()));
+}
+
+#[test]
+fn margolus_sand_conserves_and_falls() {
+    let mut manager = VoxelManager::new(6, 6, 8);
+    manager.rule = Arc::new(MargolusSandRule);
+    for x in 2..4 {
+        for z in 2..4 {
+            manager.set_voxel(x, 7, z, Some(SAND));
+        }
+    }
+
+    for _ in 0..40 {
+        manager.update();
+        assert_eq!(count(&manager), 4);
+    }
+
+    let floor = (0..6).flat_map(|x| (0..6).map(move |z| (x, z))).filter(|&(x, z)| manager.get_voxel(x, 0, z).is_some()).count();
+    assert_eq!(floor, 4);
+}
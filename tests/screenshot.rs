@@ -0,0 +1,30 @@
+use meshview::screenshot::{encode_png, flip_rows, unpremultiply};
+
+
+#[test]
+fn flips_rows_in_place() {
+    // 1 pixel wide, 3 rows
+    let mut pixels = vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3];
+    flip_rows(&mut pixels, 1);
+    assert_eq!(pixels, vec![3, 3, 3, 3, 2, 2, 2, 2, 1, 1, 1, 1]);
+}
+
+#[test]
+fn unpremultiplies_translucent_pixels() {
+    let mut pixels = vec![64, 32, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0];
+    unpremultiply(&mut pixels);
+    assert_eq!(pixels, vec![128, 64, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0]);
+}
+
+#[test]
+fn png_round_trips() {
+    let (width, height) = (3, 2);
+    let pixels: Vec<u8> = (0..width * height * 4).map(|i| (i * 10) as u8).collect();
+    let bytes = encode_png(width, height, &pixels);
+
+    let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+    let mut decoded = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut decoded).unwrap();
+    assert_eq!((info.width, info.height), (width, height));
+    assert_eq!(&decoded[..info.buffer_size()], &pixels[..]);
+}
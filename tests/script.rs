@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use egui::Color32;
+use meshview::script::VoxelScript;
+use meshview::voxel_manager::VoxelManager;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+fn count(manager: &VoxelManager) -> usize {
+    manager.voxels().iter().flatten().flatten().filter(|v| v.is_some()).count()
+}
+
+
+#[test]
+fn sand_script_matches_builtin_fall() {
+    let mut manager = VoxelManager::new(5, 5, 10);
+    manager.script = Some(Arc::new(VoxelScript::sand()));
+    manager.set_voxel(2, 9, 2, Some(SAND));
+    manager.set_voxel(2, 8, 2, Some(SAND));
+
+    while manager.update() {}
+
+    assert_eq!(manager.get_voxel(2, 0, 2), Some(SAND));
+    assert_eq!(count(&manager), 2);
+    assert!(manager.script_error.is_none());
+}
+
+#[test]
+fn script_can_transform_and_remove() {
+    let script = VoxelScript::new(r"
+        fn tick(x, y, z) {
+            if y == 0 { remove(); } else { set_color(255, 0, 0); }
+        }
+    ").unwrap();
+
+    let mut manager = VoxelManager::new(2, 2, 2);
+    manager.script = Some(Arc::new(script));
+    manager.set_voxel(0, 0, 0, Some(SAND));
+    manager.set_voxel(1, 1, 1, Some(SAND));
+
+    assert!(manager.update());
+    assert_eq!(manager.get_voxel(0, 0, 0), None);
+    assert_eq!(manager.get_voxel(1, 1, 1), Some(Color32::RED));
+}
+
+#[test]
+fn rising_voxel_moves_once_per_tick() {
+    let script = VoxelScript::new("fn tick(x, y, z) { move_by(0, 1, 0); }").unwrap();
+
+    let mut manager = VoxelManager::new(1, 1, 4);
+    manager.script = Some(Arc::new(script));
+    manager.set_voxel(0, 0, 0, Some(SAND));
+
+    manager.update();
+    assert_eq!(manager.get_voxel(0, 1, 0), Some(SAND));
+}
+
+#[test]
+fn ticks_advance_while_a_script_runs() {
+    let script = VoxelScript::new("fn tick(x, y, z) {}").unwrap();
+
+    let mut manager = VoxelManager::new(2, 2, 2);
+    manager.script = Some(Arc::new(script));
+    manager.set_voxel(0, 0, 0, Some(SAND));
+
+    for _ in 0..3 {
+        manager.update();
+    }
+    assert!(manager.script.is_some());
+    assert_eq!(manager.tick, 3);
+}
+
+#[test]
+fn script_without_tick_is_rejected() {
+    assert!(VoxelScript::new("fn step() {}").is_err());
+    assert!(VoxelScript::new("fn tick(x, y, z) {").is_err());
+}
+
+#[test]
+fn runtime_error_unloads_script() {
+    let script = VoxelScript::new("fn tick(x, y, z) { undefined_function(); }").unwrap();
+
+    let mut manager = VoxelManager::new(2, 2, 2);
+    manager.script = Some(Arc::new(script));
+    manager.set_voxel(0, 1, 0, Some(SAND));
+
+    manager.update();
+    assert!(manager.script.is_none());
+    assert!(manager.script_error.is_some());
+}
@@ -0,0 +1,32 @@
+use meshview::shader_error::{parse_info_log, ShaderError, ShaderMessage, ShaderStage};
+
+
+#[test]
+fn parses_driver_line_numbers() {
+    let mesa = parse_info_log("0:12(5): error: `foo' undeclared\n");
+    assert_eq!(mesa, vec![ShaderMessage { line: Some(12), text: "error: `foo' undeclared".to_string() }]);
+
+    let nvidia = parse_info_log("0(7) : error C0000: syntax error, unexpected '}'");
+    assert_eq!(nvidia[0].line, Some(7));
+    assert_eq!(nvidia[0].text, "error C0000: syntax error, unexpected '}'");
+
+    let angle = parse_info_log("ERROR: 0:31: 'fs_col' : undeclared identifier\nERROR: 0:31: '' : compilation terminated\n");
+    assert_eq!(angle.len(), 2);
+    assert_eq!(angle[0].line, Some(31));
+    assert_eq!(angle[0].text, "error: 'fs_col' : undeclared identifier");
+}
+
+#[test]
+fn keeps_lines_without_location() {
+    let messages = parse_info_log("\nerror: vertex shader output `fs_col' not read\n  \n");
+    assert_eq!(messages, vec![ShaderMessage { line: None, text: "error: vertex shader output `fs_col' not read".to_string() }]);
+}
+
+#[test]
+fn display_names_stage_and_file() {
+    let err = ShaderError::from_log(ShaderStage::Fragment, "src/main.frag.glsl", "0:3(1): error: syntax error");
+    assert_eq!(err.to_string(), "Failed to build fragment shader src/main.frag.glsl\n  src/main.frag.glsl:3: error: syntax error");
+
+    let err = ShaderError::unreadable(ShaderStage::Vertex, "missing.glsl", "not found");
+    assert_eq!(err.to_string(), "Failed to build vertex shader missing.glsl\n  not found");
+}
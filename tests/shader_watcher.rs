@@ -0,0 +1,38 @@
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use meshview::shader_watcher::ShaderWatcher;
+
+
+#[test]
+fn reports_each_write_once() {
+    let path = std::env::temp_dir().join(format!("shader_watcher_{}.glsl", std::process::id()));
+    std::fs::write(&path, "void main() {}").unwrap();
+
+    let mut watcher = ShaderWatcher::new([&path]);
+    assert!(!watcher.changed());
+
+    // set the time explicitly, writes within the timestamp resolution look unchanged
+    let file = File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(watcher.changed());
+}
+
+#[test]
+fn poll_waits_for_the_interval() {
+    let path = std::env::temp_dir().join(format!("shader_watcher_poll_{}.glsl", std::process::id()));
+    std::fs::write(&path, "void main() {}").unwrap();
+
+    let mut watcher = ShaderWatcher::new([&path]);
+    File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    assert!(!watcher.poll());
+
+    std::thread::sleep(ShaderWatcher::INTERVAL);
+    assert!(watcher.poll());
+
+    std::fs::remove_file(&path).unwrap();
+}
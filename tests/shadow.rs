@@ -0,0 +1,55 @@
+#![cfg(feature = "gl")]
+
+use meshview::lighting::Lighting;
+use meshview::shadow::ShadowMap;
+use meshview::voxel_manager::VOXEL_WIDTH;
+use nalgebra::{Vector3, Vector4};
+
+
+fn corners(max: Vector3<f32>) -> Vec<Vector3<f32>> {
+    (0..8).map(|i| Vector3::new(
+        if i & 1 == 0 { 0.0 } else { max.x },
+        if i & 2 == 0 { 0.0 } else { max.y },
+        if i & 4 == 0 { 0.0 } else { max.z },
+    )).collect()
+}
+
+
+#[test]
+fn light_direction_is_normalized() {
+    for (azimuth, elevation) in [(0.0, 0.0), (30.0, 60.0), (200.0, 90.0), (310.0, -45.0)] {
+        let lighting = Lighting { azimuth, elevation, ..Default::default() };
+        assert!((lighting.direction().norm() - 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn light_frustum_contains_the_grid() {
+    let max = Vector3::new(50.0, 30.0, 50.0);
+
+    for (azimuth, elevation) in [(0.0, 90.0), (30.0, 60.0), (120.0, 10.0), (270.0, 45.0)] {
+        let lighting = Lighting { azimuth, elevation, ..Default::default() };
+        let light_view_proj = ShadowMap::light_view_proj(&lighting, Vector3::zeros(), max);
+
+        for corner in corners(max) {
+            // same transform as the vertex shader
+            let world = Vector4::new(corner.x, -corner.y, corner.z, 1.0 / VOXEL_WIDTH) * VOXEL_WIDTH;
+            let clip = light_view_proj * world;
+            let ndc = clip.xyz() / clip.w;
+            assert!(ndc.iter().all(|c| c.abs() <= 1.0), "corner {corner:?} is outside the light frustum at {ndc:?}");
+        }
+    }
+}
+
+#[test]
+fn higher_voxels_are_closer_to_an_overhead_light() {
+    let max = Vector3::new(10.0, 10.0, 10.0);
+    let lighting = Lighting { elevation: 90.0, ..Default::default() };
+    let light_view_proj = ShadowMap::light_view_proj(&lighting, Vector3::zeros(), max);
+
+    let depth = |y: f32| {
+        let clip = light_view_proj * Vector4::new(5.0, -y, 5.0, 1.0 / VOXEL_WIDTH) * VOXEL_WIDTH;
+        clip.z / clip.w
+    };
+    assert!(depth(8.0) < depth(2.0));
+}
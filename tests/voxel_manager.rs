@@ -0,0 +1,510 @@
+use egui::Color32;
+use meshview::mesh_data::{MeshData, VoxelInstance};
+use meshview::voxel_manager::{Material, MeshMode, VoxelManager};
+use nalgebra::Vector3;
+use proptest::prelude::*;
+
+
+const SAND: Color32 = Color32::from_rgb(0xff, 0xe0, 0xab);
+
+
+fn count(manager: &VoxelManager) -> usize {
+    manager.voxels().iter().flatten().flatten().filter(|v| v.is_some()).count()
+}
+
+fn occupied(manager: &VoxelManager, x: i32, y: i32, z: i32) -> bool {
+    if x < 0 || y < 0 || z < 0 || x >= manager.width as i32 || y >= manager.height as i32 || z >= manager.length as i32 {
+        return false;
+    }
+    manager.get_voxel(x as usize, y as usize, z as usize).is_some()
+}
+
+/// Faces of occupied voxels not covered by another occupied voxel. The walls of the box don't hide faces.
+fn exposed_faces(manager: &VoxelManager) -> usize {
+    let mut faces = 0;
+    for x in 0..manager.width as i32 {
+        for y in 0..manager.height as i32 {
+            for z in 0..manager.length as i32 {
+                if !occupied(manager, x, y, z) {
+                    continue;
+                }
+                faces += [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+                    .iter()
+                    .filter(|(dx, dy, dz)| !occupied(manager, x + dx, y + dy, z + dz))
+                    .count();
+            }
+        }
+    }
+    faces
+}
+
+/// Total surface area of a mesh in voxel faces.
+fn face_area(mesh: &MeshData) -> f32 {
+    mesh.indicies.chunks_exact(3).map(|tri| {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| mesh.positions[i as usize]);
+        (b - a).cross(&(c - a)).norm() / 2.0
+    }).sum()
+}
+
+fn assert_in_bounds(manager: &VoxelManager) {
+    assert_eq!(manager.voxels().len(), manager.width);
+    for column in manager.voxels().iter() {
+        assert_eq!(column.len(), manager.height);
+        for row in column.iter() {
+            assert_eq!(row.len(), manager.length);
+        }
+    }
+}
+
+/// Ticks until nothing moves. Every move lowers a voxel by one layer, so the number of
+/// productive ticks is bounded by voxels * height.
+fn settle(manager: &mut VoxelManager) -> usize {
+    let limit = count(manager) * manager.height + 1;
+    let mut ticks = 0;
+    while manager.update() {
+        ticks += 1;
+        assert!(ticks <= limit, "simulation did not settle within {limit} ticks");
+    }
+    ticks
+}
+
+
+#[test]
+fn single_voxel_falls_to_floor() {
+    let mut manager = VoxelManager::new(5, 5, 10);
+    manager.set_voxel(2, 9, 2, Some(SAND));
+
+    settle(&mut manager);
+
+    assert_eq!(manager.get_voxel(2, 0, 2), Some(SAND));
+    assert_eq!(count(&manager), 1);
+}
+
+#[test]
+fn voxel_on_floor_does_not_move() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.set_voxel(1, 0, 1, Some(SAND));
+
+    assert!(!manager.update());
+    assert_eq!(manager.get_voxel(1, 0, 1), Some(SAND));
+}
+
+#[test]
+fn column_spreads_into_pile() {
+    let mut manager = VoxelManager::new(9, 9, 9);
+    for y in 0..9 {
+        manager.set_voxel(4, y, 4, Some(SAND));
+    }
+
+    settle(&mut manager);
+
+    assert_eq!(count(&manager), 9);
+    // a 9-high column can't stand on its own
+    assert!(manager.get_voxel(4, 8, 4).is_none());
+}
+
+#[test]
+fn empty_grid_is_stable_and_has_no_mesh() {
+    let mut manager = VoxelManager::new(4, 4, 4);
+
+    assert!(!manager.update());
+    assert!(manager.get_mesh().is_empty());
+}
+
+#[test]
+fn mesh_face_count_matches_exposed_faces() {
+    let mut manager = VoxelManager::new(4, 4, 4);
+    manager.set_voxel(0, 0, 0, Some(SAND));
+    manager.set_voxel(1, 0, 0, Some(SAND));
+    manager.set_voxel(1, 1, 0, Some(SAND));
+
+    // three voxels in an L touch twice, hiding four of their 18 faces
+    assert_eq!(exposed_faces(&manager), 14);
+    assert_eq!(manager.get_mesh().triangle_count(), 14 * 2);
+}
+
+#[test]
+fn faces_share_corner_vertices() {
+    let mut manager = VoxelManager::new(1, 1, 1);
+    manager.set_voxel(0, 0, 0, Some(SAND));
+
+    let mesh = manager.get_mesh();
+    assert_eq!(mesh.vertex_count(), 6 * 4);
+    assert_eq!(mesh.indicies.len(), 6 * 6);
+    // positions are in voxel units, the renderer scales them
+    assert!(mesh.positions.iter().all(|p| p.iter().all(|&c| c == 0.0 || c == 1.0)));
+}
+
+#[test]
+fn normals_point_out_of_the_solid() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.set_voxel(1, 0, 1, Some(SAND));
+    manager.set_voxel(1, 1, 1, Some(SAND));
+
+    for mode in [MeshMode::Faces, MeshMode::Greedy] {
+        manager.mesh_mode = mode;
+        let mesh = manager.get_mesh();
+        assert_eq!(mesh.normals.len(), mesh.vertex_count());
+
+        for quad in mesh.indicies.chunks_exact(6) {
+            let center = quad.iter().map(|&i| mesh.positions[i as usize]).sum::<Vector3<f32>>() / 6.0;
+            let normal = mesh.normals[quad[0] as usize];
+            let outside = (center + normal * 0.5).map(|c| c.floor() as i32);
+            assert!(!occupied(&manager, outside.x, outside.y, outside.z), "{mode:?} normal {normal:?} points into the solid");
+        }
+    }
+}
+
+#[test]
+fn corners_next_to_a_step_are_occluded() {
+    let mut manager = VoxelManager::new(3, 4, 3);
+    manager.set_voxel(1, 0, 1, Some(SAND));
+    manager.set_voxel(2, 0, 1, Some(SAND));
+    manager.set_voxel(2, 1, 1, Some(SAND));
+
+    for mode in [MeshMode::Faces, MeshMode::Greedy] {
+        manager.mesh_mode = mode;
+        let mesh = manager.get_mesh();
+        assert_eq!(mesh.occlusion.len(), mesh.vertex_count());
+
+        // top face of the lower voxel, against the wall of the upper one
+        let top: Vec<usize> = (0..mesh.vertex_count())
+            .filter(|&i| mesh.normals[i].y == 1.0 && mesh.positions[i].y == 1.0)
+            .collect();
+        assert_eq!(top.len(), 4, "{mode:?}");
+        for i in top {
+            let expected = if mesh.positions[i].x == 2.0 { 1 } else { 0 };
+            assert_eq!(mesh.occlusion[i], expected, "{mode:?} corner {:?}", mesh.positions[i]);
+        }
+    }
+}
+
+#[test]
+fn quads_split_along_the_brighter_diagonal() {
+    let corners = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.0, 1.0)];
+
+    let mut mesh = MeshData::default();
+    mesh.push_quad(corners, Vector3::y(), SAND, [0, 2, 0, 0]);
+    // both triangles share the a-d diagonal
+    assert!(mesh.indicies.chunks_exact(3).all(|tri| tri.contains(&0) && tri.contains(&3)));
+
+    let mut mesh = MeshData::default();
+    mesh.push_quad(corners, Vector3::y(), SAND, [2, 0, 0, 0]);
+    assert!(mesh.indicies.chunks_exact(3).all(|tri| tri.contains(&1) && tri.contains(&2)));
+}
+
+#[test]
+fn smooth_regions_add_up_to_the_whole_mesh() {
+    let mut manager = VoxelManager::new(8, 8, 6);
+    manager.mesh_mode = MeshMode::Smooth;
+    manager.randomize(0.3);
+
+    let whole = manager.get_mesh();
+    let halves = [([0, 0, 0], [4, 6, 8]), ([4, 0, 0], [8, 6, 8])].map(|(min, max)| manager.get_mesh_region(min, max));
+
+    assert_eq!(halves.iter().map(|mesh| mesh.triangle_count()).sum::<usize>(), whole.triangle_count());
+}
+
+#[test]
+fn smooth_floor_has_no_underside() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.set_voxel(1, 0, 1, Some(SAND));
+
+    let mesh = manager.get_smooth_mesh();
+    assert!(!mesh.is_empty());
+    assert!(mesh.positions.iter().all(|p| p.y >= 0.0));
+    assert!(mesh.normals.iter().all(|n| n.y >= 0.0));
+}
+
+#[test]
+fn instances_skip_enclosed_voxels() {
+    let mut manager = VoxelManager::new(3, 3, 3);
+    for x in 0..3 {
+        for y in 0..3 {
+            for z in 0..3 {
+                manager.set_voxel(x, y, z, Some(SAND));
+            }
+        }
+    }
+
+    let instances = manager.get_instances();
+    assert_eq!(instances.len(), 26);
+    assert!(!instances.iter().any(|instance| instance.position == [1, 1, 1]));
+
+    let data = VoxelInstance::encode(&instances);
+    assert_eq!(data.len(), instances.len() * VoxelInstance::STRIDE);
+}
+
+#[test]
+fn cube_has_outward_normals() {
+    let cube = MeshData::cube();
+    assert_eq!(cube.triangle_count(), 12);
+
+    let center = Vector3::repeat(0.5);
+    for (p, n) in cube.positions.iter().zip(cube.normals.iter()) {
+        assert!((p - center).dot(n) > 0.0);
+    }
+}
+
+#[test]
+fn resize_keeps_overlapping_voxels() {
+    let mut manager = VoxelManager::new(4, 4, 4);
+    manager.set_voxel(1, 0, 1, Some(SAND));
+    manager.set_voxel(3, 0, 3, Some(SAND));
+
+    manager.resize(2, 3, 6, true);
+
+    assert_eq!((manager.length, manager.width, manager.height), (2, 3, 6));
+    assert_in_bounds(&manager);
+    assert_eq!(manager.get_voxel(1, 0, 1), Some(SAND));
+    assert_eq!(count(&manager), 1);
+
+    manager.resize(2, 3, 6, false);
+    assert_eq!(count(&manager), 0);
+}
+
+#[test]
+fn resize_keeps_settings() {
+    let mut manager = VoxelManager::new(4, 4, 4);
+    manager.mesh_mode = MeshMode::Smooth;
+    manager.color_variation = 1.5;
+    manager.script_error = Some("oops".to_string());
+
+    manager.resize(8, 8, 8, false);
+
+    assert_eq!(manager.mesh_mode, MeshMode::Smooth);
+    assert_eq!(manager.color_variation, 1.5);
+    assert_eq!(manager.script_error.as_deref(), Some("oops"));
+}
+
+#[test]
+fn greedy_floor_is_one_quad_per_side() {
+    let mut manager = VoxelManager::new(50, 50, 30);
+    manager.mesh_mode = MeshMode::Greedy;
+    for x in 0..50 {
+        for z in 0..50 {
+            manager.set_voxel(x, 0, z, Some(SAND));
+        }
+    }
+
+    // top, bottom and four walls
+    assert_eq!(manager.get_mesh().triangle_count(), 6 * 2);
+    assert_eq!(manager.get_face_mesh().triangle_count(), exposed_faces(&manager) * 2);
+}
+
+#[test]
+fn greedy_keeps_colors_apart() {
+    let mut manager = VoxelManager::new(1, 2, 1);
+    manager.set_voxel(0, 0, 0, Some(SAND));
+    manager.set_voxel(1, 0, 0, Some(Color32::RED));
+
+    let mesh = manager.get_greedy_mesh();
+    assert_eq!(mesh.triangle_count(), exposed_faces(&manager) * 2);
+}
+
+#[test]
+fn water_shows_solids_behind_it() {
+    let water = Material::Water.colors()[0];
+    let mut manager = VoxelManager::new(1, 3, 1);
+    manager.set_voxel(0, 0, 0, Some(SAND));
+    manager.set_voxel(1, 0, 0, Some(water));
+    manager.set_voxel(2, 0, 0, Some(water));
+
+    // the sand face under the water stays, the water faces against sand and water don't
+    let (opaque, translucent) = manager.get_face_mesh().split_translucent();
+    assert_eq!(opaque.triangle_count(), 6 * 2);
+    assert_eq!(translucent.triangle_count(), (4 * 2 + 1) * 2);
+    assert!(translucent.colors.iter().all(|c| c.a() < 255));
+
+    // greedy merges the two water voxels into one box without the face against the sand
+    let (opaque, translucent) = manager.get_greedy_mesh().split_translucent();
+    assert_eq!(opaque.triangle_count(), 6 * 2);
+    assert_eq!(translucent.triangle_count(), 5 * 2);
+}
+
+#[test]
+fn smooth_water_stays_translucent() {
+    let water = Material::Water.colors()[0];
+    let mut manager = VoxelManager::new(3, 3, 3);
+    manager.mesh_mode = MeshMode::Smooth;
+    manager.set_voxel(1, 0, 1, Some(SAND));
+    manager.set_voxel(1, 1, 1, Some(water));
+
+    // the water is a box of faces in the blended pass, minus the face resting on the sand
+    let (opaque, translucent) = manager.get_mesh().split_translucent();
+    assert_eq!(translucent.triangle_count(), 5 * 2);
+    assert!(translucent.colors.iter().all(|c| *c == water));
+    assert!(!opaque.is_empty());
+    assert!(opaque.colors.iter().all(|c| c.a() == 255));
+
+    // and the sand's smooth surface is the same as without water on it
+    manager.set_voxel(1, 1, 1, None);
+    assert_eq!(opaque, manager.get_mesh());
+}
+
+#[test]
+fn water_shades_hide_each_other() {
+    let [deep, shallow] = Material::Water.colors()[..] else { panic!("water has two shades") };
+    assert_ne!(deep, shallow);
+    let mut manager = VoxelManager::new(1, 2, 1);
+    manager.set_voxel(0, 0, 0, Some(deep));
+    manager.set_voxel(1, 0, 0, Some(shallow));
+
+    // the face between the two shades isn't drawn from either side
+    let (_, translucent) = manager.get_face_mesh().split_translucent();
+    assert_eq!(translucent.triangle_count(), (6 * 2 - 2) * 2);
+}
+
+#[test]
+fn translucent_instances_encode_opaque_colors() {
+    let glass = Material::Glass.colors()[0];
+    assert!(glass.a() < 255);
+    let bytes = VoxelInstance::encode(&[VoxelInstance { position: [1, 2, 3], color: glass }]);
+
+    assert_eq!(bytes.len(), VoxelInstance::STRIDE);
+    assert_eq!(bytes[6..], glass.to_opaque().to_array());
+    // premultiplied glass would come out at about a quarter of its brightness
+    assert_eq!(bytes[6..9], [0xd8, 0xf0, 0xf0]);
+}
+
+#[test]
+fn instances_behind_water_are_kept() {
+    let water = Material::Water.colors()[0];
+    let mut manager = VoxelManager::new(3, 3, 3);
+    for x in 0..3 {
+        for y in 0..3 {
+            for z in 0..3 {
+                manager.set_voxel(x, y, z, Some(water));
+            }
+        }
+    }
+    manager.set_voxel(1, 1, 1, Some(SAND));
+
+    assert!(manager.get_instances().iter().any(|i| i.position == [1, 1, 1]));
+}
+
+#[test]
+fn translucent_triangles_sort_back_to_front() {
+    let mut mesh = MeshData::default();
+    for z in 0..4 {
+        let z = z as f32;
+        mesh.push_quad([Vector3::new(0.0, 0.0, z), Vector3::new(1.0, 0.0, z), Vector3::new(0.0, 1.0, z), Vector3::new(1.0, 1.0, z)], Vector3::z(), SAND, [0; 4]);
+    }
+    let mut appended = MeshData::default();
+    appended.append(&mesh);
+    assert_eq!(appended, mesh);
+
+    mesh.sort_back_to_front(Vector3::new(0.5, 0.5, -10.0));
+    let depths: Vec<f32> = mesh.indicies.chunks_exact(3).map(|t| mesh.positions[t[0] as usize].z).collect();
+    assert!(depths.windows(2).all(|w| w[0] >= w[1]));
+    assert_eq!(depths[0], 3.0);
+}
+
+
+/// An edge used by an odd number of triangles, so the surface isn't closed there. The floor
+/// is solid but not meshed, so piles are only open where they touch it.
+fn open_edge_above_floor(mesh: &MeshData) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let mut edges = std::collections::HashMap::new();
+    for tri in mesh.indicies.chunks_exact(3) {
+        for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    edges.into_iter()
+        .map(|((a, b), count)| (mesh.positions[a as usize], mesh.positions[b as usize], count))
+        .find(|&(a, b, count)| count % 2 == 1 && (a.y >= 0.5 || b.y >= 0.5))
+        .map(|(a, b, _)| (a, b))
+}
+
+#[test]
+fn smooth_mesh_of_single_voxel_grid_is_closed() {
+    // a grid that is one voxel, touching the floor and every wall
+    let mut manager = VoxelManager::new(1, 1, 1);
+    manager.set_voxel(0, 0, 0, Some(SAND));
+
+    let mesh = manager.get_smooth_mesh();
+    assert!(mesh.triangle_count() > 0);
+    assert_eq!(open_edge_above_floor(&mesh), None);
+}
+
+
+fn grid() -> impl Strategy<Value = VoxelManager> {
+    (1usize..7, 1usize..7, 1usize..7).prop_flat_map(|(length, width, height)| {
+        prop::collection::vec(prop::bool::weighted(0.4), length * width * height).prop_map(move |cells| {
+            let mut manager = VoxelManager::new(length, width, height);
+            for (i, filled) in cells.into_iter().enumerate() {
+                if filled {
+                    let (x, y, z) = (i / (height * length), (i / length) % height, i % length);
+                    manager.set_voxel(x, y, z, Some(SAND));
+                }
+            }
+            manager
+        })
+    })
+}
+
+proptest! {
+    #[test]
+    fn voxel_count_is_conserved(mut manager in grid(), ticks in 1usize..20) {
+        let before = count(&manager);
+        for _ in 0..ticks {
+            manager.update();
+            assert_in_bounds(&manager);
+            prop_assert_eq!(count(&manager), before);
+        }
+    }
+
+    #[test]
+    fn settled_piles_are_stable(mut manager in grid()) {
+        settle(&mut manager);
+        let settled = manager.voxels().clone();
+
+        prop_assert!(!manager.update());
+        prop_assert_eq!(manager.voxels(), &settled);
+    }
+
+    #[test]
+    fn settled_piles_have_no_floating_voxels(mut manager in grid()) {
+        settle(&mut manager);
+
+        for x in 0..manager.width {
+            for y in 1..manager.height {
+                for z in 0..manager.length {
+                    if manager.get_voxel(x, y, z).is_some() {
+                        prop_assert!(manager.get_voxel(x, y - 1, z).is_some());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_matches_exposed_faces(manager in grid()) {
+        let mesh = manager.get_mesh();
+        let faces = exposed_faces(&manager);
+
+        prop_assert_eq!(mesh.triangle_count(), faces * 2);
+        prop_assert_eq!(mesh.vertex_count(), mesh.colors.len());
+        prop_assert!(mesh.indicies.iter().all(|&i| (i as usize) < mesh.vertex_count()));
+    }
+
+    #[test]
+    fn smooth_mesh_is_closed_above_the_floor(manager in grid()) {
+        let mesh = manager.get_smooth_mesh();
+        prop_assert_eq!(mesh.normals.len(), mesh.vertex_count());
+        prop_assert_eq!(mesh.colors.len(), mesh.vertex_count());
+
+        let open = open_edge_above_floor(&mesh);
+        prop_assert!(open.is_none(), "open edge {:?} above the floor", open);
+    }
+
+    #[test]
+    fn greedy_mesh_covers_exposed_faces(manager in grid()) {
+        let greedy = manager.get_greedy_mesh();
+        let faces = manager.get_face_mesh();
+
+        prop_assert!(greedy.triangle_count() <= faces.triangle_count());
+        prop_assert!((face_area(&greedy) - exposed_faces(&manager) as f32).abs() < 1e-2);
+    }
+}